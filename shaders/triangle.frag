@@ -0,0 +1,9 @@
+#version 450
+// The color of the triangle, render::TRIANGLE_COLOR.
+// glslangValidator -V triangle.frag -o triangle.frag.spv
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = vec4(1.0, 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+// Draws the triangle of render::Scene, rendered by the exporter of a RENDER case.
+// glslangValidator -V triangle.vert -o triangle.vert.spv
+
+layout(push_constant) uniform Params {
+    vec2 positions[3];
+} params;
+
+void main() {
+    gl_Position = vec4(params.positions[gl_VertexIndex], 0.0, 1.0);
+}
@@ -0,0 +1,84 @@
+//! Imports memory exported by another process, see the `cross_process` module.
+//! Spawned by `gfx_external_memory_test --cross-process`, not meant to be run by hand.
+
+#[cfg(all(
+    unix,
+    any(
+        feature = "mock",
+        feature = "vulkan",
+        feature = "dx12",
+        feature = "metal",
+        feature = "gl",
+        feature = "empty",
+    )
+))]
+use gfx_external_memory_test::*;
+
+#[cfg(all(
+    unix,
+    any(
+        feature = "mock",
+        feature = "vulkan",
+        feature = "dx12",
+        feature = "metal",
+        feature = "gl",
+        feature = "empty",
+    )
+))]
+fn main() {
+    env_logger::init();
+    const USAGE: &str = "Usage: import_helper <socket fd> <adapter identity as JSON>";
+    let mut args = std::env::args().skip(1);
+    let socket: std::os::unix::io::RawFd = args
+        .next()
+        .and_then(|arg| arg.parse().ok())
+        .expect(USAGE);
+    // Import on the adapter that exported the memory, and on no other
+    let adapter: cross_process::AdapterIdentity = args
+        .next()
+        .and_then(|arg| serde_json::from_str(&arg).ok())
+        .expect(USAGE);
+
+    #[cfg(feature = "mock")]
+    let device = {
+        let device = mock::MockDevice::new();
+        if let Err(err) = adapter.select(&[device.adapter_info()]) {
+            log::error!("{}", err);
+            std::process::exit(2);
+        }
+        device
+    };
+    #[cfg(not(feature = "mock"))]
+    let (_instance, device) = match init_device::init_selected_device::<back::Backend>(|infos| adapter.select(infos)) {
+        Ok(instance_device) => instance_device,
+        Err(err) => {
+            log::error!("Error on `init_device`: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = cross_process::serve_import_request(&device, socket) {
+        log::error!("Error on `serve_import_request`: {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("Cross process import is only supported on unix");
+}
+
+#[cfg(all(
+    unix,
+    not(any(
+        feature = "mock",
+        feature = "vulkan",
+        feature = "dx12",
+        feature = "metal",
+        feature = "gl",
+        feature = "empty",
+    ))
+))]
+fn main() {
+    println!("You need to enable one of the backend features (vulkan/dx12/metal/gl/empty/mock) in order to run the helper");
+}
@@ -0,0 +1,419 @@
+//! Dump of what the adapter supports, without allocating anything.
+
+use gfx_hal as hal;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::matrix::{buffer_usages, external_memory_types, image_usages, TILINGS};
+use crate::report::AdapterReport;
+use crate::TestDevice;
+
+/// Images of each dimension count are queried: 1D, 2D and 3D.
+const DIMENSIONS: [u8; 3] = [1, 2, 3];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BufferCapability {
+    pub external_memory_type: String,
+    pub usage: String,
+    pub properties: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageCapability {
+    pub external_memory_type: String,
+    pub dimensions: u8,
+    pub format: String,
+    pub tiling: String,
+    pub usage: String,
+    /// The queried properties, or the error of the query.
+    pub properties: Result<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DrmModifierCapability {
+    pub drm_modifier: String,
+    pub plane_count: u32,
+    pub valid_usages: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DrmFormatCapability {
+    pub format: String,
+    pub modifiers: Vec<DrmModifierCapability>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub adapter: AdapterReport,
+    pub min_imported_host_pointer_alignment: u64,
+    pub buffers: Vec<BufferCapability>,
+    /// Queried for 1D, 2D and 3D images of each of the queried formats.
+    pub images: Vec<ImageCapability>,
+    pub drm_formats: Vec<DrmFormatCapability>,
+}
+
+/// Every format but `Undefined`.
+pub fn all_formats() -> Vec<hal::format::Format> {
+    // The variants of `Format` are numbered from 0, `Undefined`, to `NUM_FORMATS - 1`
+    (1..hal::format::NUM_FORMATS as u32)
+        .map(|index| unsafe { std::mem::transmute::<u32, hal::format::Format>(index) })
+        .collect()
+}
+
+/// The formats of `all_formats` named in the comma separated `names`, ignoring
+/// case. `None` if a name is not a format.
+pub fn parse_formats(names: &str) -> Option<Vec<hal::format::Format>> {
+    let formats = all_formats();
+    names
+        .split(',')
+        .map(|name| {
+            formats
+                .iter()
+                .copied()
+                .find(|format| format!("{:?}", format).eq_ignore_ascii_case(name.trim()))
+        })
+        .collect()
+}
+
+impl Capabilities {
+    /// Queries the buffers, and the images and DRM format modifiers of `formats`.
+    pub fn query<D: TestDevice>(device: &D, formats: &[hal::format::Format]) -> Self {
+        let mut buffers = Vec::new();
+        let mut images = Vec::new();
+        for external_memory_type in external_memory_types() {
+            for usage in buffer_usages() {
+                let properties = device.external_buffer_properties(
+                    usage,
+                    hal::memory::SparseFlags::empty(),
+                    external_memory_type,
+                );
+                buffers.push(BufferCapability {
+                    external_memory_type: format!("{:?}", external_memory_type),
+                    usage: format!("{:?}", usage),
+                    properties: format!("{:?}", properties),
+                });
+            }
+            for &dimensions in DIMENSIONS.iter() {
+                for &format in formats {
+                    for &tiling in TILINGS.iter() {
+                        for usage in image_usages() {
+                            let properties = device.external_image_properties(
+                                format,
+                                dimensions,
+                                tiling,
+                                usage,
+                                hal::image::ViewCapabilities::empty(),
+                                external_memory_type,
+                            );
+                            images.push(ImageCapability {
+                                external_memory_type: format!("{:?}", external_memory_type),
+                                dimensions,
+                                format: format!("{:?}", format),
+                                tiling: format!("{:?}", tiling),
+                                usage: format!("{:?}", usage),
+                                properties: properties
+                                    .map(|properties| format!("{:?}", properties))
+                                    .map_err(|err| err.to_string()),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let drm_formats = formats
+            .iter()
+            .map(|&format| DrmFormatCapability {
+                format: format!("{:?}", format),
+                modifiers: device
+                    .format_properties(Some(format))
+                    .drm_format_properties
+                    .into_iter()
+                    .map(|properties| DrmModifierCapability {
+                        drm_modifier: format!("{:?}", properties.drm_modifier),
+                        plane_count: properties.plane_count,
+                        valid_usages: format!("{:?}", properties.valid_usages),
+                    })
+                    .collect(),
+            })
+            .filter(|drm_format| !drm_format.modifiers.is_empty())
+            .collect();
+
+        Self {
+            adapter: device.adapter_info().into(),
+            min_imported_host_pointer_alignment: device.min_imported_host_pointer_alignment(),
+            buffers,
+            images,
+            drm_formats,
+        }
+    }
+}
+
+pub fn write_capabilities(
+    writer: &mut dyn Write,
+    format: DumpFormat,
+    capabilities: &Capabilities,
+) -> io::Result<()> {
+    match format {
+        DumpFormat::Text => write_tables(writer, capabilities, &TEXT),
+        DumpFormat::Markdown => write_tables(writer, capabilities, &MARKDOWN),
+        DumpFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, capabilities)?;
+            writeln!(writer)
+        }
+    }
+}
+
+/// How a table is laid out.
+struct TableStyle {
+    title: fn(&str) -> String,
+    /// A row, given the width of each column in characters.
+    row: fn(&[&str], &[usize]) -> String,
+    /// Line between the header and the rows, given the width of each column.
+    separator: fn(&[usize]) -> String,
+}
+
+const TEXT: TableStyle = TableStyle {
+    title: |title| format!("{}:", title),
+    row: |cells, widths| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, &width)| format!("{:<width$}  ", cell, width = width))
+            .collect::<String>()
+            .trim_end()
+            .into()
+    },
+    separator: |widths| widths.iter().map(|&width| "-".repeat(width)).collect::<Vec<_>>().join("  "),
+};
+
+const MARKDOWN: TableStyle = TableStyle {
+    title: |title| format!("## {}", title),
+    // Flags are printed as `A | B`, which would split the cell
+    row: |cells, _| {
+        let cells: Vec<String> = cells.iter().map(|cell| cell.replace('|', "\\|")).collect();
+        format!("| {} |", cells.join(" | "))
+    },
+    separator: |widths| format!("|{}", "---|".repeat(widths.len())),
+};
+
+fn write_table(
+    writer: &mut dyn Write,
+    style: &TableStyle,
+    title: &str,
+    header: &[&str],
+    rows: impl Iterator<Item = Vec<String>>,
+) -> io::Result<()> {
+    let rows: Vec<Vec<String>> = rows.collect();
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    writeln!(writer, "{}", (style.title)(title))?;
+    writeln!(writer)?;
+    writeln!(writer, "{}", (style.row)(header, &widths))?;
+    writeln!(writer, "{}", (style.separator)(&widths))?;
+    for row in &rows {
+        let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+        writeln!(writer, "{}", (style.row)(&cells, &widths))?;
+    }
+    writeln!(writer)
+}
+
+fn write_tables(
+    writer: &mut dyn Write,
+    capabilities: &Capabilities,
+    style: &TableStyle,
+) -> io::Result<()> {
+    let adapter = &capabilities.adapter;
+    writeln!(
+        writer,
+        "{}",
+        (style.title)(&format!("{} ({})", adapter.name, adapter.device_type))
+    )?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "min_imported_host_pointer_alignment: {}",
+        capabilities.min_imported_host_pointer_alignment
+    )?;
+    writeln!(writer)?;
+
+    write_table(
+        writer,
+        style,
+        "Buffers",
+        &["Handle type", "Usage", "Properties"],
+        capabilities.buffers.iter().map(|buffer| {
+            vec![
+                buffer.external_memory_type.clone(),
+                buffer.usage.clone(),
+                buffer.properties.clone(),
+            ]
+        }),
+    )?;
+    write_table(
+        writer,
+        style,
+        "Images",
+        &["Handle type", "Dimensions", "Format", "Tiling", "Usage", "Properties"],
+        capabilities.images.iter().map(|image| {
+            vec![
+                image.external_memory_type.clone(),
+                format!("{}D", image.dimensions),
+                image.format.clone(),
+                image.tiling.clone(),
+                image.usage.clone(),
+                match &image.properties {
+                    Ok(properties) => properties.clone(),
+                    Err(err) => format!("Error: {}", err),
+                },
+            ]
+        }),
+    )?;
+    write_table(
+        writer,
+        style,
+        "DRM format modifiers",
+        &["Format", "Modifier", "Planes", "Valid usages"],
+        capabilities.drm_formats.iter().flat_map(|drm_format| {
+            drm_format.modifiers.iter().map(move |modifier| {
+                vec![
+                    drm_format.format.clone(),
+                    modifier.drm_modifier.clone(),
+                    modifier.plane_count.to_string(),
+                    modifier.valid_usages.clone(),
+                ]
+            })
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::format::Format;
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            adapter: AdapterReport {
+                name: "GPU".into(),
+                vendor: 1,
+                device: 2,
+                device_type: "DiscreteGpu".into(),
+            },
+            min_imported_host_pointer_alignment: 4096,
+            buffers: vec![BufferCapability {
+                external_memory_type: "OpaqueFd".into(),
+                usage: "TRANSFER_SRC | TRANSFER_DST".into(),
+                properties: "EXPORTABLE | IMPORTABLE".into(),
+            }],
+            images: vec![ImageCapability {
+                external_memory_type: "DmaBuf".into(),
+                dimensions: 3,
+                format: "Rgba8Srgb".into(),
+                tiling: "Optimal".into(),
+                usage: "SAMPLED".into(),
+                properties: Err("Format not supported".into()),
+            }],
+            drm_formats: vec![DrmFormatCapability {
+                format: "Rgba8Srgb".into(),
+                modifiers: vec![DrmModifierCapability {
+                    drm_modifier: "DrmModifier(0)".into(),
+                    plane_count: 1,
+                    valid_usages: "TRANSFER_DST | SAMPLED".into(),
+                }],
+            }],
+        }
+    }
+
+    fn write(format: DumpFormat) -> String {
+        let mut output = Vec::new();
+        write_capabilities(&mut output, format, &capabilities()).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn markdown_tables_escape_pipes() {
+        let output = write(DumpFormat::Markdown);
+        for row in [
+            "| OpaqueFd | TRANSFER_SRC \\| TRANSFER_DST | EXPORTABLE \\| IMPORTABLE |",
+            "| DmaBuf | 3D | Rgba8Srgb | Optimal | SAMPLED | Error: Format not supported |",
+            "| Rgba8Srgb | DrmModifier(0) | 1 | TRANSFER_DST \\| SAMPLED |",
+        ] {
+            assert!(output.lines().any(|line| line == row), "{}", output);
+        }
+
+        // Each row of a table has as many cells as its header
+        for table in output.split("\n\n").filter(|table| table.starts_with('|')) {
+            let columns: Vec<usize> = table
+                .lines()
+                .map(|line| line.replace("\\|", "").matches('|').count())
+                .collect();
+            assert!(columns.iter().all(|&count| count == columns[0]), "{}", table);
+        }
+    }
+
+    #[test]
+    fn text_tables_align_columns() {
+        let output = write(DumpFormat::Text);
+        let table: Vec<&str> = output
+            .split("\n\n")
+            .skip_while(|&block| block != "Buffers:")
+            .nth(1)
+            .unwrap()
+            .lines()
+            .collect();
+        assert_eq!(
+            table,
+            [
+                "Handle type  Usage                        Properties",
+                "-----------  ---------------------------  -----------------------",
+                "OpaqueFd     TRANSFER_SRC | TRANSFER_DST  EXPORTABLE | IMPORTABLE",
+            ]
+        );
+    }
+
+    #[test]
+    fn all_formats_are_queried_by_default() {
+        let formats = all_formats();
+        assert!(!formats.contains(&Format::Undefined));
+        let ycbcr_formats = crate::ycbcr::YCBCR_FORMATS.iter().map(|ycbcr_format| &ycbcr_format.format);
+        for format in crate::matrix::FORMATS.iter().chain(ycbcr_formats) {
+            assert!(formats.contains(format), "{:?}", format);
+        }
+        let names: std::collections::HashSet<_> = formats.iter().map(|format| format!("{:?}", format)).collect();
+        assert_eq!(names.len(), formats.len());
+    }
+
+    #[test]
+    fn formats_are_parsed_by_name() {
+        assert_eq!(
+            parse_formats("rgba8srgb, G8B8r82plane420Unorm"),
+            Some(vec![Format::Rgba8Srgb, Format::G8B8r82plane420Unorm])
+        );
+        assert_eq!(parse_formats("Rgba8Srgb,Rgba9Srgb"), None);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn images_of_every_dimension_are_queried() {
+        let device = crate::mock::MockDevice::new();
+        let capabilities = Capabilities::query(&device, &[Format::Rgba8Srgb]);
+        for &dimensions in DIMENSIONS.iter() {
+            let queried = capabilities.images.iter().any(|image| image.dimensions == dimensions);
+            assert!(queried, "{}D", dimensions);
+        }
+        assert_eq!(capabilities.drm_formats.len(), 1);
+    }
+}
@@ -0,0 +1,209 @@
+//! Test cases loaded from a file, so new cases can be added without recompiling.
+//!
+//! The format is chosen from the extension: `.ron`, `.toml` or `.json`. The file
+//! holds a list of `TestCase` under the `case` key, for example in TOML:
+//!
+//! ```toml
+//! [[case]]
+//! name = "OPAQUE_FD"
+//! [case.parameters.Buffer]
+//! external_memory_type = "OpaqueFd"
+//! buffer_usage = { bits = 128 }
+//! buffer_flags = { bits = 0 }
+//! ```
+//!
+//! `--save-cases` writes the built-in test cases in any of these formats, which is
+//! the easiest way to start a new file, and `cases/example.toml` shows the payload
+//! and image options. TOML integers are signed 64-bit, so the seeds above
+//! `i64::MAX` can only be saved in RON or JSON.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::TestCase;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseFileFormat {
+    Ron,
+    Toml,
+    Json,
+}
+
+impl CaseFileFormat {
+    pub fn from_path(path: &Path) -> Result<Self, CaseFileError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Ok(Self::Ron),
+            Some("toml") => Ok(Self::Toml),
+            Some("json") => Ok(Self::Json),
+            _ => Err(CaseFileError::UnknownFormat(path.into())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CaseFile {
+    #[serde(rename = "case", default)]
+    cases: Vec<TestCase>,
+}
+
+#[derive(Debug)]
+pub enum CaseFileError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    Serialize(String),
+    UnknownFormat(PathBuf),
+}
+
+impl std::fmt::Display for CaseFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "Failed to access `{}`: {}", path.display(), err),
+            Self::Parse(path, err) => write!(f, "Failed to parse `{}`: {}", path.display(), err),
+            Self::Serialize(err) => write!(f, "Failed to serialize the test cases: {}", err),
+            Self::UnknownFormat(path) => write!(
+                f,
+                "Unknown format of `{}`, expected a .ron, .toml or .json file",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CaseFileError {}
+
+pub fn load_test_cases(path: &Path) -> Result<Vec<TestCase>, CaseFileError> {
+    let format = CaseFileFormat::from_path(path)?;
+    let text =
+        std::fs::read_to_string(path).map_err(|err| CaseFileError::Io(path.into(), err))?;
+    let parse_error = |err: &dyn std::fmt::Display| CaseFileError::Parse(path.into(), err.to_string());
+    let case_file: CaseFile = match format {
+        CaseFileFormat::Ron => ron::de::from_str(&text).map_err(|err| parse_error(&err))?,
+        CaseFileFormat::Toml => toml::from_str(&text).map_err(|err| parse_error(&err))?,
+        CaseFileFormat::Json => serde_json::from_str(&text).map_err(|err| parse_error(&err))?,
+    };
+    Ok(case_file.cases)
+}
+
+pub fn save_test_cases(path: &Path, cases: &[TestCase]) -> Result<(), CaseFileError> {
+    let format = CaseFileFormat::from_path(path)?;
+    let case_file = CaseFile {
+        cases: cases.to_vec(),
+    };
+    let serialize_error = |err: &dyn std::fmt::Display| CaseFileError::Serialize(err.to_string());
+    let text = match format {
+        CaseFileFormat::Ron => {
+            ron::ser::to_string_pretty(&case_file, ron::ser::PrettyConfig::default())
+                .map_err(|err| serialize_error(&err))?
+        }
+        // Going through `toml::Value` puts the plain values before the tables,
+        // as TOML requires
+        CaseFileFormat::Toml => {
+            let large_seed = cases.iter().find_map(|case| {
+                let seed = case.data.seed().filter(|&seed| seed > i64::MAX as u64)?;
+                Some((seed, &case.name))
+            });
+            if let Some((seed, name)) = large_seed {
+                return Err(CaseFileError::Serialize(format!(
+                    "The seed {} of `{}` does not fit in a TOML integer, use a .ron or .json file",
+                    seed, name
+                )));
+            }
+            toml::Value::try_from(&case_file)
+                .and_then(|value| toml::to_string_pretty(&value))
+                .map_err(|err| serialize_error(&err))?
+        }
+        CaseFileFormat::Json => {
+            serde_json::to_string_pretty(&case_file).map_err(|err| serialize_error(&err))?
+        }
+    };
+    std::fs::write(path, text).map_err(|err| CaseFileError::Io(path.into(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::Pattern;
+
+    fn temp_path(name: &str, extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "gfx_external_memory_test_{}_{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ))
+    }
+
+    fn seeded_buffer(seed: u64) -> TestCase {
+        TestCase {
+            data: crate::DataTest {
+                size: Some(4099),
+                pattern: Pattern::Random { seed: Some(seed) },
+            },
+            ..crate::buffer_test_cases().remove(0)
+        }
+    }
+
+    /// Saves `cases` to a file of each format and loads them back.
+    fn round_trip(name: &str, cases: &[TestCase]) {
+        let _fds = crate::lock_fds();
+        let expected = serde_json::to_value(cases).unwrap();
+        for extension in &["ron", "toml", "json"] {
+            let path = temp_path(name, extension);
+            save_test_cases(&path, cases).unwrap();
+            let loaded = load_test_cases(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(serde_json::to_value(loaded.unwrap()).unwrap(), expected, "{}", extension);
+        }
+    }
+
+    #[test]
+    fn seeded_buffer_round_trips() {
+        round_trip("seeded_buffer", &[seeded_buffer(i64::MAX as u64)]);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn drm_modifiers_round_trip() {
+        use gfx_hal as hal;
+        let drm_modifiers = vec![
+            hal::format::DrmModifier::from(0),
+            hal::format::DrmModifier::from((1 << 56) | 4),
+        ];
+        let test_case = TestCase {
+            verification: crate::Verification::Unchecked,
+            ..TestCase::new(
+                "DMA_BUF modifiers",
+                crate::image_parameters(
+                    hal::external_memory::ExternalImageMemoryType::DmaBuf(drm_modifiers),
+                    hal::format::Format::G8B8r82plane420Unorm,
+                ),
+            )
+        };
+        round_trip("drm_modifiers", &[test_case]);
+    }
+
+    #[test]
+    fn large_seeds_are_not_saved_to_toml() {
+        let _fds = crate::lock_fds();
+        let cases = [seeded_buffer(u64::MAX)];
+        let path = temp_path("large_seed", "toml");
+        assert!(matches!(save_test_cases(&path, &cases), Err(CaseFileError::Serialize(_))));
+        assert!(!path.exists());
+
+        let path = temp_path("large_seed", "json");
+        save_test_cases(&path, &cases).unwrap();
+        let loaded = load_test_cases(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap()[0].data.seed(), Some(u64::MAX));
+    }
+
+    #[test]
+    fn example_loads() {
+        let _fds = crate::lock_fds();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("cases/example.toml");
+        let cases = load_test_cases(&path).unwrap();
+        assert_eq!(cases.len(), 4);
+        assert_eq!(cases[0].data.seed(), Some(12345));
+    }
+}
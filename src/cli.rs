@@ -0,0 +1,390 @@
+//! Command line options of the test binary.
+
+use gfx_hal as hal;
+use std::path::PathBuf;
+
+use crate::capabilities::DumpFormat;
+use crate::payload::Pattern;
+use crate::report::ReportFormat;
+use crate::{ImportMode, RunConfig, TestCase};
+
+pub const USAGE: &str = "\
+Usage: gfx_external_memory_test [OPTIONS]
+       gfx_external_memory_test capabilities [--format <text|markdown|json>]
+                                             [--formats <FORMAT,...>]
+                                             [--adapter <INDEX|NAME>] [--output <PATH>]
+
+The `capabilities` command prints the external memory properties of every handle
+type, usage, format, image dimension and tiling, the minimum imported host
+pointer alignment and the DRM format modifiers, without allocating anything.
+`--formats` only queries the listed formats, named like `Rgba8Srgb` (default:
+every format).
+
+Options:
+    --cases <PATH>              Load the test cases from a .ron, .toml or .json file
+    --save-cases <PATH>         Save the test cases to a .ron, .toml or .json file
+                                and exit
+    --matrix                    Generate the test cases from every combination of
+                                handle type, usage, format, tiling and kind that the
+                                adapter claims to support
+    --list                      List the test cases and exit
+    --filter <PATTERN>          Only run the test cases whose name, handle type or
+                                resource kind contains PATTERN (can be repeated)
+    --adapter <INDEX|NAME>      Run on the adapter with this index or name
+    --payload-size <SIZE>       Size of the buffer payloads, in bytes or with a K,
+                                M or G suffix (default: 4K, or the file size)
+    --pattern <PATTERN>         Fill the buffer payloads with `random` bytes, a
+                                `counter` or the content of `file:<PATH>`
+                                (default: random)
+    --seed <N>                  Seed of the random payloads (default: a new one
+                                for every run, printed in the report)
+    --repeat <N>                Run every test case N times (default: 1)
+    --report <text|json|junit>  Format of the report (default: text)
+    --output <PATH>             Write the report to PATH instead of stdout
+    --cross-process             Import the memory in the `import_helper` process
+    --fault-injection           Check that failing calls do not leak
+    --check-consistency         Run every stage, even the unsupported ones, and
+                                report the differences between the claimed
+                                external memory properties and the results
+    --mismatch-dir <PATH>       Write the expected, read and difference PNGs of
+                                the image round trips that do not match to PATH
+    --help                      Print this message
+
+The exit code is 1 if any stage failed, or with `--check-consistency` if any
+claim is inconsistent, and 2 on invalid options or if no adapter matches
+`--adapter`.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Run,
+    Capabilities,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command: Command,
+    /// Format of the `capabilities` dump.
+    pub dump_format: DumpFormat,
+    /// Image formats the `capabilities` dump queries, all of them if `None`.
+    pub formats: Option<Vec<hal::format::Format>>,
+    /// File to load the test cases from, instead of the built-in ones.
+    pub cases: Option<PathBuf>,
+    pub save_cases: Option<PathBuf>,
+    pub matrix: bool,
+    pub list: bool,
+    /// A test case runs only if it matches all the filters.
+    pub filters: Vec<String>,
+    pub adapter: Option<String>,
+    pub repeat: usize,
+    pub report: ReportFormat,
+    pub output: Option<PathBuf>,
+    pub import_mode: ImportMode,
+    pub fault_injection: bool,
+    pub check_consistency: bool,
+    pub mismatch_dir: Option<PathBuf>,
+    /// Override the payload of every test case.
+    pub payload_size: Option<u64>,
+    pub pattern: Option<Pattern>,
+    pub seed: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            command: Command::Run,
+            dump_format: DumpFormat::Text,
+            formats: None,
+            cases: None,
+            save_cases: None,
+            matrix: false,
+            list: false,
+            filters: Vec::new(),
+            adapter: None,
+            repeat: 1,
+            report: ReportFormat::Text,
+            output: None,
+            import_mode: ImportMode::SameProcess,
+            fault_injection: false,
+            check_consistency: false,
+            mismatch_dir: None,
+            payload_size: None,
+            pattern: None,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    Help,
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownArgument(String),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Help => f.write_str(USAGE),
+            Self::MissingValue(arg) => write!(f, "Missing value for `{}`", arg),
+            Self::InvalidValue(arg, value) => write!(f, "Invalid value `{}` for `{}`", value, arg),
+            Self::UnknownArgument(arg) => write!(f, "Unknown argument `{}`", arg),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl CliError {
+    /// 0 for `--help`, 2 for invalid options.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Help => 0,
+            _ => 2,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, CliError> {
+        let mut args = args.peekable();
+        let mut options = Self::default();
+        if args.peek().map(String::as_str) == Some("capabilities") {
+            args.next();
+            options.command = Command::Capabilities;
+        }
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| CliError::MissingValue(arg.clone()));
+            match arg.as_str() {
+                "--cases" => options.cases = Some(value()?.into()),
+                "--save-cases" => options.save_cases = Some(value()?.into()),
+                "--matrix" => options.matrix = true,
+                "--list" => options.list = true,
+                "--filter" => options.filters.push(value()?),
+                "--adapter" => options.adapter = Some(value()?),
+                "--payload-size" => {
+                    let size = value()?;
+                    options.payload_size = match parse_size(&size) {
+                        Some(size) => Some(size),
+                        None => return Err(CliError::InvalidValue(arg, size)),
+                    }
+                }
+                "--pattern" => {
+                    let pattern = value()?;
+                    options.pattern = Some(match pattern.as_str() {
+                        "random" => Pattern::Random { seed: None },
+                        "counter" => Pattern::Counter,
+                        _ => match pattern.strip_prefix("file:") {
+                            Some(path) => Pattern::File(path.into()),
+                            None => return Err(CliError::InvalidValue(arg, pattern)),
+                        },
+                    })
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = match seed.parse() {
+                        Ok(seed) => Some(seed),
+                        Err(_) => return Err(CliError::InvalidValue(arg, seed)),
+                    }
+                }
+                "--repeat" => {
+                    let repeat = value()?;
+                    options.repeat = match repeat.parse() {
+                        Ok(repeat) if repeat > 0 => repeat,
+                        _ => return Err(CliError::InvalidValue(arg, repeat)),
+                    }
+                }
+                "--report" => {
+                    options.report = match value()?.as_str() {
+                        "text" => ReportFormat::Text,
+                        "json" => ReportFormat::Json,
+                        "junit" => ReportFormat::JUnit,
+                        other => return Err(CliError::InvalidValue(arg, other.into())),
+                    }
+                }
+                "--format" => {
+                    options.dump_format = match value()?.as_str() {
+                        "text" => DumpFormat::Text,
+                        "markdown" => DumpFormat::Markdown,
+                        "json" => DumpFormat::Json,
+                        other => return Err(CliError::InvalidValue(arg, other.into())),
+                    }
+                }
+                "--formats" => {
+                    let formats = value()?;
+                    options.formats = match crate::capabilities::parse_formats(&formats) {
+                        Some(formats) => Some(formats),
+                        None => return Err(CliError::InvalidValue(arg, formats)),
+                    }
+                }
+                "--output" => options.output = Some(value()?.into()),
+                "--cross-process" => options.import_mode = ImportMode::CrossProcess,
+                "--fault-injection" => options.fault_injection = true,
+                "--check-consistency" => options.check_consistency = true,
+                "--mismatch-dir" => options.mismatch_dir = Some(value()?.into()),
+                "--help" | "-h" => return Err(CliError::Help),
+                _ => return Err(CliError::UnknownArgument(arg)),
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn run_config(&self) -> RunConfig {
+        RunConfig {
+            import_mode: self.import_mode,
+            ignore_properties: self.check_consistency,
+            mismatch_dir: self.mismatch_dir.clone(),
+        }
+    }
+
+    /// The test cases matching all the filters.
+    pub fn filter(&self, test_cases: Vec<TestCase>) -> Vec<TestCase> {
+        test_cases
+            .into_iter()
+            .filter(|test_case| self.filters.iter().all(|filter| test_case.matches(filter)))
+            .collect()
+    }
+
+    /// Overrides the payload of the test cases with the payload options.
+    pub fn apply_payload(&self, test_cases: Vec<TestCase>) -> Vec<TestCase> {
+        test_cases
+            .into_iter()
+            .map(|mut test_case| {
+                if let Some(size) = self.payload_size {
+                    test_case.data.size = Some(size);
+                }
+                if let Some(pattern) = &self.pattern {
+                    test_case.data.pattern = pattern.clone();
+                }
+                if let (Some(seed), Pattern::Random { seed: case_seed }) = (self.seed, &mut test_case.data.pattern) {
+                    *case_seed = Some(seed);
+                }
+                test_case
+            })
+            .collect()
+    }
+}
+
+/// Parses a size in bytes, with an optional K, M or G binary suffix.
+fn parse_size(size: &str) -> Option<u64> {
+    let (number, shift) = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => (&size[..size.len() - 1], 10),
+        'M' => (&size[..size.len() - 1], 20),
+        'G' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Index of the adapter selected by `selector`: either its index or a part of its
+/// name, ignoring case. A part of several names is ambiguous, unless it is the
+/// whole name of one of them.
+pub fn select_adapter(adapters: &[hal::adapter::AdapterInfo], selector: &str) -> Result<usize, String> {
+    if let Ok(index) = selector.parse::<usize>() {
+        return if index < adapters.len() {
+            Ok(index)
+        } else {
+            Err(format!("No adapter has the index {}", index))
+        };
+    }
+    let lowercase = selector.to_lowercase();
+    let matching: Vec<usize> = (0..adapters.len())
+        .filter(|&index| adapters[index].name.to_lowercase().contains(&lowercase))
+        .collect();
+    match matching[..] {
+        [] => Err(format!("No adapter matches `{}`", selector)),
+        [index] => Ok(index),
+        _ => match matching.iter().find(|&&index| adapters[index].name.to_lowercase() == lowercase) {
+            Some(&index) => Ok(index),
+            None => Err(format!(
+                "`{}` matches several adapters ({}), select one by index",
+                selector,
+                matching
+                    .iter()
+                    .map(|&index| format!("{}: {}", index, adapters[index].name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        Options::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn sizes_take_binary_suffixes() {
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("4099"), Some(4099));
+        assert_eq!(parse_size("4K"), Some(4 << 10));
+        assert_eq!(parse_size("3m"), Some(3 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        for size in &["", "K", "1.5M", "-1", "1T", "4KB", "18446744073709551615K"] {
+            assert_eq!(parse_size(size), None, "{}", size);
+        }
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = parse(&["capabilities", "--format", "json", "--formats", "Rgba8Srgb"]).unwrap();
+        assert_eq!(options.command, Command::Capabilities);
+        assert_eq!(options.dump_format, DumpFormat::Json);
+        assert_eq!(options.formats, Some(vec![hal::format::Format::Rgba8Srgb]));
+
+        let args = ["--payload-size", "1M", "--pattern", "counter", "--seed", "7", "--repeat", "3"];
+        let options = parse(&args).unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.payload_size, Some(1 << 20));
+        assert_eq!(options.pattern, Some(Pattern::Counter));
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.repeat, 3);
+    }
+
+    #[test]
+    fn bad_options_exit_with_2() {
+        let invalid = |arg: &str, value: &str| Err(CliError::InvalidValue(arg.into(), value.into()));
+        assert_eq!(parse(&["--payload-size", "lots"]).map(|_| ()), invalid("--payload-size", "lots"));
+        assert_eq!(parse(&["--pattern", "zeros"]).map(|_| ()), invalid("--pattern", "zeros"));
+        assert_eq!(parse(&["--seed", "-1"]).map(|_| ()), invalid("--seed", "-1"));
+        assert_eq!(parse(&["--repeat", "0"]).map(|_| ()), invalid("--repeat", "0"));
+        assert_eq!(parse(&["--report", "html"]).map(|_| ()), invalid("--report", "html"));
+        assert_eq!(parse(&["--formats", "Rgba8Srgb,"]).map(|_| ()), invalid("--formats", "Rgba8Srgb,"));
+        assert_eq!(parse(&["--seed"]).map(|_| ()), Err(CliError::MissingValue("--seed".into())));
+        assert_eq!(parse(&["--verbose"]).map(|_| ()), Err(CliError::UnknownArgument("--verbose".into())));
+        for args in &[&["--seed", "x"][..], &["--seed"], &["--verbose"]] {
+            assert_eq!(parse(args).unwrap_err().exit_code(), 2);
+        }
+        assert_eq!(parse(&["--help"]).unwrap_err().exit_code(), 0);
+    }
+
+    fn adapter(name: &str) -> hal::adapter::AdapterInfo {
+        hal::adapter::AdapterInfo {
+            name: name.into(),
+            vendor: 0,
+            device: 0,
+            device_type: hal::adapter::DeviceType::DiscreteGpu,
+        }
+    }
+
+    #[test]
+    fn adapters_are_selected_by_index_or_name() {
+        let adapters = [
+            adapter("AMD Radeon RX 6800"),
+            adapter("Intel UHD Graphics 630"),
+            adapter("AMD Radeon"),
+        ];
+        assert_eq!(select_adapter(&adapters, "1"), Ok(1));
+        assert!(select_adapter(&adapters, "3").is_err());
+        assert_eq!(select_adapter(&adapters, "intel"), Ok(1));
+        assert!(select_adapter(&adapters, "nvidia").is_err());
+        // Both AMD adapters match, but one has exactly this name
+        assert_eq!(select_adapter(&adapters, "amd radeon"), Ok(2));
+        let err = select_adapter(&adapters, "radeon").unwrap_err();
+        assert!(err.contains("0: AMD Radeon RX 6800") && err.contains("2: AMD Radeon"), "{}", err);
+    }
+}
@@ -1,26 +1,165 @@
 use gfx_hal as hal;
-use hal::device::Device;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use crate::{HalError, TestDevice};
 
-pub enum Resource<T: gfx_hal::Backend> {
-    Buffer(T::Buffer),
-    Image(T::Image)
+/// Number of objects currently alive on a device.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LiveObjects {
+    pub buffers: usize,
+    pub images: usize,
+    pub memories: usize,
 }
-impl<T: gfx_hal::Backend> Resource<T> {
-    pub fn image(&self)->&T::Image {
+
+impl LiveObjects {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub parameters: Parameters,
+    /// Payload written by the exporter of a buffer and checked by the importer.
+    /// Images are checked with their `image_content` instead.
+    #[serde(default)]
+    pub data: crate::DataTest,
+    #[serde(default)]
+    pub image_content: crate::image_data::ImageContent,
+    /// The memory types the resources may be allocated from.
+    #[serde(default)]
+    pub memory_filter: MemoryFilter,
+    #[serde(default)]
+    pub verification: Verification,
+    /// Each plane of the multi-planar image is bound to its own memory object.
+    /// See `disjoint`.
+    #[serde(default)]
+    pub disjoint: bool,
+}
+
+/// How the data of a test case is written and checked.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Verification {
+    /// Through mappings of the memory of the resources.
+    Map,
+    /// Through a staging buffer and copies on the graphics queue. See `transfer`.
+    Transfer,
+    /// By compute shaders, for storage buffers. See `compute`.
+    Compute,
+    /// Rendered to by the exporter and copied out by the importer, for color
+    /// attachments. See `render`.
+    Render,
+    /// Nothing is written nor checked, for optimally tiled images whose usage
+    /// allows no transfers and for DRM format modifiers whose memory planes are
+    /// not the planes of the format.
+    Unchecked,
+}
+
+impl Default for Verification {
+    fn default() -> Self {
+        Self::Map
+    }
+}
+
+impl Verification {
+    /// Properties every memory type the data is written to and read from needs.
+    pub fn required_properties(self) -> hal::memory::Properties {
         match self {
-            Self::Image(image)=>image,
-            _=>panic!()
+            Self::Map => hal::memory::Properties::CPU_VISIBLE,
+            Self::Transfer | Self::Compute | Self::Render | Self::Unchecked => hal::memory::Properties::empty(),
         }
     }
-    pub fn buffer(&self)->&T::Buffer {
-        match self {
-            Self::Buffer(buffer)=>buffer,
-            _=>panic!()
+}
+
+/// Properties a memory type must have, and must not have, on top of the ones
+/// the `Verification` of the test case requires.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryFilter {
+    pub required: hal::memory::Properties,
+    pub excluded: hal::memory::Properties,
+}
+
+impl MemoryFilter {
+    /// Non-coherent memory types, where a missing flush or invalidation shows.
+    pub const NON_COHERENT: Self = Self {
+        required: hal::memory::Properties::empty(),
+        excluded: hal::memory::Properties::COHERENT,
+    };
+
+    /// Device local memory types, mappable or not.
+    pub const DEVICE_LOCAL: Self = Self {
+        required: hal::memory::Properties::DEVICE_LOCAL,
+        excluded: hal::memory::Properties::empty(),
+    };
+
+    pub fn matches(&self, properties: hal::memory::Properties, verification: Verification) -> bool {
+        properties.contains(verification.required_properties() | self.required)
+            && !properties.intersects(self.excluded)
+    }
+}
+
+/// A memory type a test case runs on.
+#[derive(Debug, Clone)]
+pub struct MemoryTypeInfo {
+    /// Index in `MemoryProperties::memory_types`.
+    pub index: usize,
+    pub properties: hal::memory::Properties,
+    pub heap_index: usize,
+    pub heap_flags: hal::memory::HeapFlags,
+    pub heap_size: u64,
+}
+
+impl std::fmt::Display for MemoryTypeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({:?}), heap {} ({:?}, {} bytes)",
+            self.index, self.properties, self.heap_index, self.heap_flags, self.heap_size
+        )
+    }
+}
+
+impl Default for MemoryFilter {
+    fn default() -> Self {
+        Self {
+            required: hal::memory::Properties::empty(),
+            excluded: hal::memory::Properties::empty(),
+        }
+    }
+}
+
+impl TestCase {
+    /// A case with the defaults a case file gets for the omitted fields: the
+    /// default payload and image content, any memory type and mappings.
+    pub fn new(name: impl Into<String>, parameters: Parameters) -> Self {
+        Self {
+            name: name.into(),
+            parameters,
+            data: crate::DataTest::default(),
+            image_content: crate::image_data::ImageContent::default(),
+            memory_filter: MemoryFilter::default(),
+            verification: Verification::default(),
+            disjoint: false,
         }
     }
+
+    /// Whether `filter` is contained, ignoring case, in the name, the handle type or
+    /// the resource kind of the test case.
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        [
+            self.name.clone(),
+            format!("{:?}", self.parameters.external_memory_type()),
+            self.parameters.resource_name().into(),
+        ]
+        .iter()
+        .any(|field| field.to_lowercase().contains(&filter))
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Parameters {
     Image{
         external_memory_type: hal::external_memory::ExternalImageMemoryType,
@@ -39,51 +178,123 @@ pub enum Parameters {
     }
 }
 
-pub fn read_memory<T: Default>(
-    device: &gfx_backend_vulkan::Device,
-    memory: &mut <gfx_backend_vulkan::Backend as gfx_hal::Backend>::Memory,
-) -> T {
-    // Gather data from the imported memory
-    let mapping = match unsafe { device.map_memory(memory, hal::memory::Segment::ALL) } {
-        Ok(pointer) => pointer,
-        Err(err) => panic!("Failed to `map_memory`:{:#?}", err),
-    };
-    let mut data = T::default();
-    let data_len = std::mem::size_of::<T>() as u64;
-    unsafe {
-        std::ptr::copy_nonoverlapping(
-            mapping,
-            std::slice::from_mut(&mut data).as_mut_ptr() as *mut u8,
-            data_len as usize,
-        )
-    };
+impl Parameters {
+    pub fn resource_name(&self) -> &'static str {
+        match self {
+            Self::Image { .. } => "Image",
+            Self::Buffer { .. } => "Buffer",
+        }
+    }
+
+    /// The DRM format modifiers of a DmaBuf image created with explicit modifiers.
+    pub fn drm_modifiers(&self) -> Option<&[hal::format::DrmModifier]> {
+        match self {
+            #[cfg(any(target_os = "linux", target_os = "android", doc))]
+            Self::Image {
+                external_memory_type: hal::external_memory::ExternalImageMemoryType::DmaBuf(drm_modifiers),
+                ..
+            } if !drm_modifiers.is_empty() => Some(drm_modifiers),
+            _ => None,
+        }
+    }
+
+    pub fn external_memory_type(&self) -> hal::external_memory::ExternalMemoryType {
+        match self {
+            Self::Image { external_memory_type, .. } => external_memory_type.external_memory_type(),
+            Self::Buffer { external_memory_type, .. } => *external_memory_type,
+        }
+    }
+}
+
+/// Payloads are copied from and compared with the mapped memory in chunks of this
+/// size.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// The smallest segment covering `len` bytes from `offset` whose bounds are
+/// multiples of `non_coherent_atom_size`, as flushes and invalidations require.
+/// The memory must be large enough for the rounded up size.
+pub fn atom_aligned_segment<D: TestDevice>(device: &D, offset: u64, len: u64) -> hal::memory::Segment {
+    let atom_size = device.non_coherent_atom_size().max(1);
+    let start = offset / atom_size * atom_size;
+    let end = (offset + len + atom_size - 1) / atom_size * atom_size;
+    hal::memory::Segment {
+        offset: start,
+        size: Some(end - start),
+    }
+}
+
+/// Copies the `len` bytes of `data` to the start of the memory and flushes them.
+pub fn write_memory<D: TestDevice>(
+    device: &D,
+    memory: &mut D::Memory,
+    data: &mut dyn Read,
+    len: u64,
+) -> Result<(), HalError> {
+    let mapping = unsafe { device.map_memory(memory, hal::memory::Segment::ALL) }?;
+    let mut copied = Ok(());
+    let mut offset = 0;
+    while offset < len {
+        let chunk_len = (len - offset).min(CHUNK_SIZE as u64) as usize;
+        let chunk = unsafe { std::slice::from_raw_parts_mut(mapping.add(offset as usize), chunk_len) };
+        if let Err(err) = data.read_exact(chunk) {
+            copied = Err(err);
+            break;
+        }
+        offset += chunk_len as u64;
+    }
+    let flushed = unsafe { device.flush_memory(&*memory, atom_aligned_segment(device, 0, len)) };
     unsafe { device.unmap_memory(memory) };
-    data
-}
-
-pub fn write_memory<T: Default>(
-    device: &gfx_backend_vulkan::Device,
-    memory: &mut <gfx_backend_vulkan::Backend as gfx_hal::Backend>::Memory,
-    data: &T,
-) {
-    // Write data on the memory
-    let data_len = std::mem::size_of::<T>() as u64;
-    let mapping = unsafe {
-        device
-            .map_memory(memory, hal::memory::Segment::ALL)
-            .unwrap()
-    };
-    unsafe {
-        std::ptr::copy_nonoverlapping(
-            std::slice::from_ref(data).as_ptr() as *const u8,
-            mapping,
-            data_len as usize,
-        )
-    };
-    unsafe {
-        device
-            .flush_mapped_memory_ranges(std::iter::once((&*memory, hal::memory::Segment::ALL)))
-            .unwrap()
-    };
+    copied?;
+    flushed.map_err(Into::into)
+}
+
+/// Invalidates the first `len` bytes of the memory and compares them with `data`,
+/// reporting the first difference.
+pub fn check_memory<D: TestDevice>(
+    device: &D,
+    memory: &mut D::Memory,
+    data: &mut dyn Read,
+    len: u64,
+) -> Result<(), HalError> {
+    let mapping = unsafe { device.map_memory(memory, hal::memory::Segment::ALL) }?;
+    if let Err(err) = unsafe { device.invalidate_memory(&*memory, atom_aligned_segment(device, 0, len)) } {
+        unsafe { device.unmap_memory(memory) };
+        return Err(err.into());
+    }
+    let mut expected = vec![0u8; (len.min(CHUNK_SIZE as u64)) as usize];
+    let mut checked = Ok(());
+    let mut offset = 0;
+    while offset < len {
+        let chunk_len = (len - offset).min(CHUNK_SIZE as u64) as usize;
+        let expected = &mut expected[..chunk_len];
+        if let Err(err) = data.read_exact(expected) {
+            checked = Err(err.into());
+            break;
+        }
+        let read = unsafe { std::slice::from_raw_parts(mapping.add(offset as usize), chunk_len) };
+        if let Some(index) = (0..chunk_len).find(|&index| read[index] != expected[index]) {
+            checked = Err(HalError::from(format!(
+                "Byte {} of {}: expected {:#04x}, read {:#04x}",
+                offset + index as u64,
+                len,
+                expected[index],
+                read[index]
+            )));
+            break;
+        }
+        offset += chunk_len as u64;
+    }
     unsafe { device.unmap_memory(memory) };
+    checked
+}
+
+/// Returns the file descriptor owned by `memory`, if any.
+#[cfg(unix)]
+pub fn platform_memory_fd(memory: &hal::external_memory::PlatformMemory) -> Option<std::os::unix::io::RawFd> {
+    use std::os::unix::io::AsRawFd;
+    match memory {
+        hal::external_memory::PlatformMemory::Fd(fd) => Some(fd.as_raw_fd()),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
 }
@@ -0,0 +1,184 @@
+//! Buffers written and checked by compute shaders, the way a renderer accesses
+//! them, instead of through mappings.
+//!
+//! The exporter fills its buffer with `fill_payload.comp`, the importer runs
+//! `check_payload.comp` over its buffer, which counts the wrong words in a small
+//! result buffer the CPU reads back. The payload is generated from the seed of
+//! the test case, so only random payloads are supported.
+
+use gfx_hal as hal;
+use hal::external_memory::ExternalMemoryType;
+
+use crate::payload::Pattern;
+use crate::{
+    write_memory, DataTest, ExternalResource, HalError, MemoryFilter, Parameters,
+    Resource, TestCase, TestDevice, Verification,
+};
+
+/// Invocations in a group, the `local_size_x` of the shaders.
+pub const GROUP_SIZE: u32 = 64;
+/// Groups in a dispatch, the minimum `max_compute_work_group_count`.
+const MAX_GROUP_COUNT: u32 = 65535;
+
+const FILL_PAYLOAD: &[u8] = include_bytes!("../shaders/fill_payload.spv");
+const CHECK_PAYLOAD: &[u8] = include_bytes!("../shaders/check_payload.spv");
+
+/// The compute shaders, all with the push constants `[seed, first_word,
+/// word_count]` and the buffer holding the payload as binding 0 of set 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shader {
+    /// Writes the payload.
+    FillPayload,
+    /// Checks the payload, binding 1 is a `[mismatches, first_mismatch]` buffer.
+    CheckPayload,
+}
+
+impl Shader {
+    pub fn spirv(self) -> Vec<u32> {
+        let bytes = match self {
+            Self::FillPayload => FILL_PAYLOAD,
+            Self::CheckPayload => CHECK_PAYLOAD,
+        };
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+}
+
+/// Word `index` of the payload generated from `seed`, as the shaders compute it.
+pub fn payload_word(index: u32, seed: u32) -> u32 {
+    let mut x = index.wrapping_mul(0x9e37_79b9) ^ seed;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x045d_9f3b);
+    x ^ (x >> 16)
+}
+
+/// Why the compute shaders can not generate the payload of `data`, of `len`
+/// bytes, if they can not.
+pub fn unsupported_payload(data: &DataTest, len: u64) -> Option<String> {
+    if !matches!(data.pattern, Pattern::Random { seed: Some(_) }) {
+        return Some("Compute shaders only generate random payloads with a seed".into());
+    }
+    if len % 4 != 0 || len / 4 > u32::MAX as u64 {
+        return Some(format!("Compute shaders write payloads of up to 4 Gi words, not {} bytes", len));
+    }
+    None
+}
+
+/// The seed and the number of words of the payload of `data`.
+fn payload(data: &DataTest) -> Result<(u32, u32), HalError> {
+    let len = data.payload_size()?;
+    if let Some(reason) = unsupported_payload(data, len) {
+        return Err(reason.into());
+    }
+    Ok((data.seed().unwrap_or_default() as u32, (len / 4) as u32))
+}
+
+/// Runs `shader` over every word of the payload, in as many dispatches as needed.
+fn dispatch_payload<D: TestDevice>(
+    device: &D,
+    shader: Shader,
+    buffers: &[&D::Buffer],
+    seed: u32,
+    word_count: u32,
+) -> Result<(), HalError> {
+    let mut first_word = 0;
+    while first_word < word_count {
+        let group_count = ((word_count - first_word + GROUP_SIZE - 1) / GROUP_SIZE).min(MAX_GROUP_COUNT);
+        unsafe { device.dispatch(shader, buffers, &[seed, first_word, word_count], group_count) }?;
+        first_word = first_word.saturating_add(group_count * GROUP_SIZE);
+    }
+    Ok(())
+}
+
+/// Writes the payload of `test_case` on the buffer of `resource`.
+pub fn write_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &ExternalResource<D>,
+) -> Result<(), HalError> {
+    let (seed, word_count) = payload(&test_case.data)?;
+    dispatch_payload(device, Shader::FillPayload, &[resource.buffer()?], seed, word_count)
+}
+
+/// Checks the payload of `test_case` on the buffer of `resource`, reporting the
+/// number of wrong words and the first one.
+pub fn check_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &ExternalResource<D>,
+) -> Result<(), HalError> {
+    let (seed, word_count) = payload(&test_case.data)?;
+
+    // `[mismatches, first_mismatch]`, starting from no mismatch
+    let result_len = 8;
+    let atom_size = device.non_coherent_atom_size().max(1);
+    let (buffer, memory) =
+        unsafe { device.create_staging_buffer((result_len + atom_size - 1) / atom_size * atom_size) }?;
+    let mut result = ExternalResource::new(device, Resource::Buffer(buffer), memory);
+    let initial: Vec<u8> = [0u32, u32::MAX].iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+    write_memory(device, result.memory_mut().unwrap(), &mut &initial[..], result_len)?;
+
+    dispatch_payload(device, Shader::CheckPayload, &[resource.buffer()?, result.buffer()?], seed, word_count)?;
+
+    let mut read = [0u8; 8];
+    read_result(device, &mut result, &mut read)?;
+    let mismatches = u32::from_le_bytes([read[0], read[1], read[2], read[3]]);
+    let first_mismatch = u32::from_le_bytes([read[4], read[5], read[6], read[7]]);
+    if mismatches == 0 {
+        return Ok(());
+    }
+    Err(format!(
+        "{} wrong words of {}. Word {}: expected {:#010x}",
+        mismatches,
+        word_count,
+        first_mismatch,
+        payload_word(first_mismatch, seed)
+    )
+    .into())
+}
+
+/// Reads the result buffer written by `check_payload.comp`.
+fn read_result<D: TestDevice>(
+    device: &D,
+    result: &mut ExternalResource<D>,
+    read: &mut [u8; 8],
+) -> Result<(), HalError> {
+    let memory = result.memory_mut().unwrap();
+    let mapping = unsafe { device.map_memory(memory, hal::memory::Segment::ALL) }?;
+    let invalidated = unsafe { device.invalidate_memory(&*memory, hal::memory::Segment::ALL) };
+    if invalidated.is_ok() {
+        read.copy_from_slice(unsafe { std::slice::from_raw_parts(mapping, read.len()) });
+    }
+    unsafe { device.unmap_memory(memory) };
+    invalidated.map_err(Into::into)
+}
+
+/// Storage buffer cases for the handle types shared between devices, on device
+/// local memory types and checked by compute shaders.
+pub fn compute_test_cases() -> Vec<TestCase> {
+    let mut external_memory_types = Vec::new();
+    #[cfg(unix)]
+    external_memory_types.push(("OPAQUE_FD", ExternalMemoryType::OpaqueFd));
+    #[cfg(windows)]
+    external_memory_types.push(("OPAQUE_WIN32", ExternalMemoryType::OpaqueWin32));
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    external_memory_types.push(("DMA_BUF", ExternalMemoryType::DmaBuf));
+
+    external_memory_types
+        .into_iter()
+        .map(|(name, external_memory_type)| TestCase {
+            memory_filter: MemoryFilter::DEVICE_LOCAL,
+            verification: Verification::Compute,
+            ..TestCase::new(
+                format!("{} COMPUTE", name),
+                Parameters::Buffer {
+                    external_memory_type,
+                    buffer_usage: hal::buffer::Usage::STORAGE,
+                    buffer_flags: hal::memory::SparseFlags::empty(),
+                },
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,332 @@
+//! Comparison between what the external memory properties claim and what actually
+//! works.
+//!
+//! The test cases are run with `RunConfig::ignore_properties`, so every stage is
+//! tried, then each capability of each case is classified.
+
+use gfx_hal as hal;
+use hal::external_memory::ExternalMemoryProperties;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::report::{escape_xml, AdapterReport, ReportFormat};
+use crate::{TestResult, Tests};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Capability {
+    Export,
+    Import,
+    ExportFromImported,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 3] = [
+        Capability::Export,
+        Capability::Import,
+        Capability::ExportFromImported,
+    ];
+
+    pub fn property(self) -> ExternalMemoryProperties {
+        match self {
+            Self::Export => ExternalMemoryProperties::EXPORTABLE,
+            Self::Import => ExternalMemoryProperties::IMPORTABLE,
+            Self::ExportFromImported => ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED,
+        }
+    }
+
+    /// The stages that must succeed for the capability to work.
+    fn stages(self, tests: &Tests) -> Vec<&TestResult> {
+        match self {
+            Self::Export => vec![&tests.create_allocate_external_resource, &tests.export_memory],
+            Self::Import => vec![&tests.import_external_resource],
+            Self::ExportFromImported => vec![&tests.export_from_imported],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Claim {
+    ClaimedAndWorks,
+    /// Driver bug.
+    ClaimedButFails,
+    /// The driver under-reports its capabilities.
+    NotClaimedButWorks,
+    /// Not claimed, and failing when tried.
+    NotClaimedAndFails,
+    /// Not claimed, and a previous stage failed so it could not be tried.
+    NotClaimedNotTried,
+    /// Claimed, but a previous stage failed so it could not be tried.
+    ClaimedNotTried,
+}
+
+impl Claim {
+    /// Whether the claim and the behavior disagree.
+    pub fn is_inconsistent(self) -> bool {
+        matches!(self, Self::ClaimedButFails | Self::NotClaimedButWorks)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CapabilityCheck {
+    pub name: String,
+    pub resource: &'static str,
+    pub capability: Capability,
+    pub claim: Claim,
+    /// The first error of the capability stages.
+    pub error: Option<String>,
+}
+
+pub fn check(tests: &Tests) -> Vec<CapabilityCheck> {
+    Capability::ALL
+        .iter()
+        .map(|&capability| {
+            let claimed = tests
+                .properties
+                .map_or(false, |properties| properties.contains(capability.property()));
+            let stages = capability.stages(tests);
+            let works = stages.iter().all(|result| matches!(result, TestResult::Success));
+            let error = stages.iter().find_map(|result| match result {
+                TestResult::Failed(error) => Some(error.to_string()),
+                _ => None,
+            });
+            let claim = match (claimed, works) {
+                (true, true) => Claim::ClaimedAndWorks,
+                (true, false) if error.is_some() => Claim::ClaimedButFails,
+                (true, false) => Claim::ClaimedNotTried,
+                (false, true) => Claim::NotClaimedButWorks,
+                (false, false) if error.is_some() => Claim::NotClaimedAndFails,
+                (false, false) => Claim::NotClaimedNotTried,
+            };
+            CapabilityCheck {
+                name: tests.name.clone(),
+                resource: tests.parameters.resource_name(),
+                capability,
+                claim,
+                error,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsistencyReport {
+    pub adapter: AdapterReport,
+    pub checks: Vec<CapabilityCheck>,
+}
+
+impl ConsistencyReport {
+    pub fn new(adapter: hal::adapter::AdapterInfo, tests: &[Tests]) -> Self {
+        Self {
+            adapter: adapter.into(),
+            checks: tests.iter().flat_map(check).collect(),
+        }
+    }
+
+    pub fn inconsistencies(&self) -> impl Iterator<Item = &CapabilityCheck> {
+        self.checks.iter().filter(|check| check.claim.is_inconsistent())
+    }
+
+    fn count(&self, claim: Claim) -> usize {
+        self.checks.iter().filter(|check| check.claim == claim).count()
+    }
+}
+
+pub fn write_consistency_report(
+    writer: &mut dyn Write,
+    format: ReportFormat,
+    report: &ConsistencyReport,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Text => write_text(writer, report),
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, report)?;
+            writeln!(writer)
+        }
+        ReportFormat::JUnit => write_junit(writer, report),
+    }
+}
+
+fn write_text(writer: &mut dyn Write, report: &ConsistencyReport) -> io::Result<()> {
+    writeln!(writer, "Adapter: {}", report.adapter.name)?;
+    for &claim in [
+        Claim::ClaimedAndWorks,
+        Claim::ClaimedButFails,
+        Claim::NotClaimedButWorks,
+        Claim::NotClaimedAndFails,
+        Claim::ClaimedNotTried,
+        Claim::NotClaimedNotTried,
+    ]
+    .iter()
+    {
+        writeln!(writer, "{:?}: {}", claim, report.count(claim))?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "Inconsistencies:")?;
+    for check in report.inconsistencies() {
+        write!(
+            writer,
+            "{} {}: {:?} {:?}",
+            check.resource, check.name, check.capability, check.claim
+        )?;
+        match &check.error {
+            Some(error) => writeln!(writer, " ({})", error)?,
+            None => writeln!(writer)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes one `testcase` per capability, failing the inconsistent ones.
+fn write_junit(writer: &mut dyn Write, report: &ConsistencyReport) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuite name="{}" tests="{}" failures="{}">"#,
+        escape_xml(&report.adapter.name),
+        report.checks.len(),
+        report.inconsistencies().count()
+    )?;
+    for check in &report.checks {
+        write!(
+            writer,
+            r#"  <testcase classname="{}.{}" name="{:?}""#,
+            check.resource,
+            escape_xml(&check.name),
+            check.capability
+        )?;
+        if check.claim.is_inconsistent() {
+            writeln!(writer, ">")?;
+            writeln!(
+                writer,
+                r#"    <failure message="{:?}">{}</failure>"#,
+                check.claim,
+                escape_xml(check.error.as_deref().unwrap_or_default())
+            )?;
+            writeln!(writer, "  </testcase>")?;
+        } else {
+            writeln!(writer, "/>")?;
+        }
+    }
+    writeln!(writer, "</testsuite>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SkipReason;
+    use Claim::*;
+
+    const EXPORTABLE: ExternalMemoryProperties = ExternalMemoryProperties::EXPORTABLE;
+    const IMPORTABLE: ExternalMemoryProperties = ExternalMemoryProperties::IMPORTABLE;
+    const FROM_IMPORTED: ExternalMemoryProperties = ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED;
+
+    fn ok() -> TestResult {
+        TestResult::Success
+    }
+
+    fn fail() -> TestResult {
+        TestResult::failed("call", String::from("error"), None)
+    }
+
+    fn not_reached() -> TestResult {
+        TestResult::Skipped(SkipReason::NotReached)
+    }
+
+    fn not_supported() -> TestResult {
+        TestResult::Skipped(SkipReason::NotSupported {
+            properties: EXPORTABLE,
+            required: IMPORTABLE,
+        })
+    }
+
+    #[test]
+    fn claims_are_classified() {
+        let none = ExternalMemoryProperties::empty();
+        // Properties, create and allocate, export, import, export from imported,
+        // then the claims of the export, import and export from imported
+        let table: Vec<(Option<ExternalMemoryProperties>, [TestResult; 4], [Claim; 3])> = vec![
+            (
+                Some(EXPORTABLE | IMPORTABLE),
+                [ok(), ok(), ok(), not_reached()],
+                [ClaimedAndWorks, ClaimedAndWorks, NotClaimedNotTried],
+            ),
+            (
+                Some(EXPORTABLE),
+                [ok(), fail(), not_reached(), not_reached()],
+                [ClaimedButFails, NotClaimedNotTried, NotClaimedNotTried],
+            ),
+            (
+                Some(EXPORTABLE),
+                [ok(), ok(), fail(), not_reached()],
+                [ClaimedAndWorks, NotClaimedAndFails, NotClaimedNotTried],
+            ),
+            (
+                Some(EXPORTABLE),
+                [ok(), ok(), not_supported(), not_reached()],
+                [ClaimedAndWorks, NotClaimedNotTried, NotClaimedNotTried],
+            ),
+            (
+                Some(IMPORTABLE),
+                [ok(), ok(), fail(), not_reached()],
+                [NotClaimedButWorks, ClaimedButFails, NotClaimedNotTried],
+            ),
+            (
+                Some(none),
+                [fail(), not_reached(), not_reached(), not_reached()],
+                [NotClaimedAndFails, NotClaimedNotTried, NotClaimedNotTried],
+            ),
+            (
+                Some(EXPORTABLE | IMPORTABLE | FROM_IMPORTED),
+                [fail(), not_reached(), not_reached(), not_reached()],
+                [ClaimedButFails, ClaimedNotTried, ClaimedNotTried],
+            ),
+            (
+                Some(IMPORTABLE | FROM_IMPORTED),
+                [ok(), ok(), ok(), fail()],
+                [NotClaimedButWorks, ClaimedAndWorks, ClaimedButFails],
+            ),
+            // A failed query claims nothing
+            (
+                None,
+                [ok(), ok(), ok(), ok()],
+                [NotClaimedButWorks, NotClaimedButWorks, NotClaimedButWorks],
+            ),
+        ];
+
+        let buffer = crate::buffer_test_cases().remove(0);
+        for (row, (properties, results, claims)) in table.into_iter().enumerate() {
+            let [create, export, import, export_from_imported] = results;
+            let mut tests = Tests::new(buffer.name.clone(), buffer.parameters.clone());
+            tests.properties = properties;
+            tests.create_allocate_external_resource = create;
+            tests.export_memory = export;
+            tests.import_external_resource = import;
+            tests.export_from_imported = export_from_imported;
+
+            let checks = check(&tests);
+            let capabilities: Vec<Capability> = checks.iter().map(|check| check.capability).collect();
+            assert_eq!(capabilities, Capability::ALL);
+            let classified: Vec<Claim> = checks.iter().map(|check| check.claim).collect();
+            assert_eq!(classified, claims, "Row {}", row);
+            for check in &checks {
+                let failed = matches!(check.claim, ClaimedButFails | NotClaimedAndFails);
+                assert_eq!(check.error.is_some(), failed, "Row {}: {:?}", row, check);
+            }
+        }
+    }
+
+    #[test]
+    fn only_disagreements_are_inconsistent() {
+        let inconsistent = [ClaimedButFails, NotClaimedButWorks];
+        for &claim in &[
+            ClaimedAndWorks,
+            ClaimedButFails,
+            NotClaimedButWorks,
+            NotClaimedAndFails,
+            NotClaimedNotTried,
+            ClaimedNotTried,
+        ] {
+            assert_eq!(claim.is_inconsistent(), inconsistent.contains(&claim), "{:?}", claim);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Import of exported memory by another process.
+//!
+//! The exporter spawns the `import_helper` binary and sends it an `ImportRequest`
+//! together with the exported file descriptor (as SCM_RIGHTS ancillary data) over a
+//! `SOCK_SEQPACKET` Unix socket. The helper creates its own instance and device,
+//! imports the memory, checks the data and replies with an `ImportResponse`.
+
+use gfx_hal as hal;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+
+use crate::{check_data, import_resource, TestCase, TestDevice};
+
+const HELPER_NAME: &str = "import_helper";
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// How long the helper may take to create its device, import the memory and
+/// check the data.
+const HELPER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaneLayout {
+    pub slice: std::ops::Range<u64>,
+    pub row_pitch: u64,
+    pub array_pitch: u64,
+    pub depth_pitch: u64,
+}
+
+/// Serializable `DrmFormatImageProperties`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrmProperties {
+    pub drm_modifier: hal::format::DrmModifier,
+    pub plane_layouts: Vec<PlaneLayout>,
+}
+
+impl From<hal::image::DrmFormatImageProperties> for DrmProperties {
+    fn from(properties: hal::image::DrmFormatImageProperties) -> Self {
+        Self {
+            drm_modifier: properties.drm_modifier,
+            plane_layouts: properties
+                .plane_layouts
+                .into_iter()
+                .map(|footprint| PlaneLayout {
+                    slice: footprint.slice,
+                    row_pitch: footprint.row_pitch,
+                    array_pitch: footprint.array_pitch,
+                    depth_pitch: footprint.depth_pitch,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<DrmProperties> for hal::image::DrmFormatImageProperties {
+    fn from(properties: DrmProperties) -> Self {
+        Self {
+            drm_modifier: properties.drm_modifier,
+            plane_layouts: properties
+                .plane_layouts
+                .into_iter()
+                .map(|layout| hal::image::SubresourceFootprint {
+                    slice: layout.slice,
+                    row_pitch: layout.row_pitch,
+                    array_pitch: layout.array_pitch,
+                    depth_pitch: layout.depth_pitch,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The adapter the helper has to open: the one of the exporter. Names are not
+/// unique, two identical GPUs have the same one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdapterIdentity {
+    pub name: String,
+    pub vendor: usize,
+    pub device: usize,
+}
+
+impl AdapterIdentity {
+    pub fn matches(&self, info: &hal::adapter::AdapterInfo) -> bool {
+        self.name == info.name && self.vendor == info.vendor && self.device == info.device
+    }
+
+    /// Index of the only adapter of `adapters` matching the identity. Fails if
+    /// several do, since the helper could open another one than the exporter.
+    pub fn select(&self, adapters: &[hal::adapter::AdapterInfo]) -> Result<usize, String> {
+        let mut matching = adapters.iter().enumerate().filter(|(_, info)| self.matches(info));
+        match (matching.next(), matching.next()) {
+            (Some((index, _)), None) => Ok(index),
+            (None, _) => Err(format!("No adapter is {:?}", self)),
+            (Some(_), Some(_)) => Err(format!("Several adapters are {:?}", self)),
+        }
+    }
+}
+
+impl From<hal::adapter::AdapterInfo> for AdapterIdentity {
+    fn from(info: hal::adapter::AdapterInfo) -> Self {
+        Self {
+            name: info.name,
+            vendor: info.vendor,
+            device: info.device,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRequest {
+    /// The resource to import and the data the exporter wrote on it.
+    pub test_case: TestCase,
+    /// Size of the buffer to import, unused by images.
+    pub size: u64,
+    pub drm_properties: Option<DrmProperties>,
+    pub mismatch_dir: Option<std::path::PathBuf>,
+    /// The memory type mask of the exporter. The helper opens the same adapter, so
+    /// the indices match.
+    pub memory_types: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportResponse {
+    pub import_external_resource: Result<(), String>,
+    /// `None` if the import failed.
+    pub data_check: Option<Result<(), String>>,
+}
+
+/// Spawns the helper process on `adapter`, hands it `fd` and waits for its
+/// verdict, for at most `HELPER_TIMEOUT`.
+pub fn import_in_helper(
+    request: &ImportRequest,
+    fd: RawFd,
+    adapter: &AdapterIdentity,
+) -> io::Result<ImportResponse> {
+    let (socket, helper_socket) = socket_pair()?;
+
+    let helper_path = std::env::current_exe()?.with_file_name(HELPER_NAME);
+    let mut command = std::process::Command::new(&helper_path);
+    command.arg(helper_socket.0.to_string()).arg(serde_json::to_string(adapter)?);
+    unsafe {
+        let helper_fd = helper_socket.0;
+        command.pre_exec(move || {
+            // Let the helper inherit its end of the socket
+            if libc::fcntl(helper_fd, libc::F_SETFD, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Failed to spawn `{}`: {}", helper_path.display(), err),
+        )
+    })?;
+    drop(helper_socket);
+
+    let response = set_receive_timeout(socket.0, HELPER_TIMEOUT)
+        .and_then(|_| send_message(socket.0, &serde_json::to_vec(request)?, Some(fd)))
+        .and_then(|_| receive_message(socket.0).map_err(timed_out))
+        .and_then(|(message, _)| Ok(serde_json::from_slice(&message)?));
+
+    // A helper that did not answer may be stuck, in the driver for instance
+    if response.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    match response {
+        Ok(response) => Ok(response),
+        Err(err) => Err(io::Error::new(
+            err.kind(),
+            format!("{} (helper exited with {})", err, status),
+        )),
+    }
+}
+
+/// Helper side: receives a request on `socket`, imports the memory, checks the data
+/// and sends back the response.
+pub fn serve_import_request<D: TestDevice>(device: &D, socket: RawFd) -> io::Result<()> {
+    let (message, fd) = receive_message(socket)?;
+    let request: ImportRequest = serde_json::from_slice(&message)?;
+    let fd = fd.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "No file descriptor received")
+    })?;
+    let exported = crate::ExportedMemory::new(hal::external_memory::PlatformMemory::Fd(fd.into()));
+
+    let response = import(device, request, exported);
+    send_message(socket, &serde_json::to_vec(&response)?, None)
+}
+
+fn import<D: TestDevice>(
+    device: &D,
+    request: ImportRequest,
+    mut exported: crate::ExportedMemory,
+) -> ImportResponse {
+    let mut resource = match import_resource(
+        device,
+        &request.test_case.parameters,
+        exported.take().unwrap(),
+        request.drm_properties.map(Into::into),
+        request.memory_types,
+        request.size,
+    ) {
+        Ok(resource) => resource,
+        Err(err) => {
+            return ImportResponse {
+                import_external_resource: Err(err.to_string()),
+                data_check: None,
+            }
+        }
+    };
+    exported.release();
+
+    let data_check = check_data(device, &request.test_case, &mut resource, request.mismatch_dir.as_deref())
+        .map_err(|err| err.to_string());
+    ImportResponse {
+        import_external_resource: Ok(()),
+        data_check: Some(data_check),
+    }
+}
+
+/// A receive timeout reported as such rather than as `WouldBlock`.
+fn timed_out(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("The helper did not answer within {:?}", HELPER_TIMEOUT),
+        ),
+        _ => err,
+    }
+}
+
+fn set_receive_timeout(socket: RawFd, timeout: std::time::Duration) -> io::Result<()> {
+    let timeval = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    if unsafe {
+        libc::setsockopt(
+            socket,
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeval as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Closes the wrapped file descriptor on drop.
+struct Socket(RawFd);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn socket_pair() -> io::Result<(Socket, Socket)> {
+    let mut fds = [0; 2];
+    if unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((Socket(fds[0]), Socket(fds[1])))
+}
+
+pub fn send_message(socket: RawFd, message: &[u8], fd: Option<RawFd>) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: message.as_ptr() as *mut libc::c_void,
+        iov_len: message.len(),
+    };
+    // u64 keeps the control buffer aligned for `cmsghdr`
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if let Some(fd) = fd {
+        let fd_size = std::mem::size_of::<RawFd>() as u32;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(fd_size) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+        }
+    }
+
+    if unsafe { libc::sendmsg(socket, &msg, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn receive_message(socket: RawFd) -> io::Result<(Vec<u8>, Option<RawFd>)> {
+    let mut message = vec![0u8; MAX_MESSAGE_SIZE];
+    let mut iov = libc::iovec {
+        iov_base: message.as_mut_ptr() as *mut libc::c_void,
+        iov_len: message.len(),
+    };
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if len == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    message.truncate(len as usize);
+
+    let mut fd = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                fd = Some(std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((message, fd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_at_start(fd: RawFd, data: &[u8]) {
+        let written = unsafe { libc::pwrite(fd, data.as_ptr() as *const libc::c_void, data.len(), 0) };
+        assert_eq!(written, data.len() as isize, "{}", io::Error::last_os_error());
+    }
+
+    fn read_at_start(fd: RawFd, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        let read = unsafe { libc::pread(fd, data.as_mut_ptr() as *mut libc::c_void, len, 0) };
+        assert_eq!(read, len as isize, "{}", io::Error::last_os_error());
+        data
+    }
+
+    fn adapter_info(name: &str, vendor: usize, device: usize) -> hal::adapter::AdapterInfo {
+        hal::adapter::AdapterInfo {
+            name: name.into(),
+            vendor,
+            device,
+            device_type: hal::adapter::DeviceType::DiscreteGpu,
+        }
+    }
+
+    #[test]
+    fn adapter_identity_selects_only_the_same_adapter() {
+        let adapters = [
+            adapter_info("GPU", 0x10de, 1),
+            adapter_info("GPU", 0x1002, 1),
+            adapter_info("GPU", 0x10de, 2),
+        ];
+        let identity = AdapterIdentity::from(adapters[1].clone());
+        assert_eq!(identity.select(&adapters), Ok(1));
+        assert!(identity.select(&adapters[..1]).is_err());
+
+        let twins = [adapter_info("GPU", 0x10de, 1), adapter_info("GPU", 0x10de, 1)];
+        assert!(AdapterIdentity::from(twins[0].clone()).select(&twins).is_err());
+    }
+
+    #[test]
+    fn fd_and_message_go_through_the_socket() {
+        let _fds = crate::lock_fds();
+        let (sender, receiver) = socket_pair().unwrap();
+        let memfd = unsafe {
+            libc::memfd_create(b"cross_process_test\0".as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC)
+        };
+        assert!(memfd >= 0, "{}", io::Error::last_os_error());
+        let memfd = Socket(memfd);
+        write_at_start(memfd.0, b"exported memory");
+
+        let payload = serde_json::to_vec(&ImportResponse {
+            import_external_resource: Ok(()),
+            data_check: Some(Err("mismatch".into())),
+        })
+        .unwrap();
+        send_message(sender.0, &payload, Some(memfd.0)).unwrap();
+        let (message, fd) = receive_message(receiver.0).unwrap();
+        assert_eq!(message, payload);
+        let received = Socket(fd.expect("No file descriptor received"));
+        assert_ne!(received.0, memfd.0);
+
+        // The received descriptor is a duplicate of the sent one: it sees the
+        // data written before, and its writes show through the original
+        assert_eq!(read_at_start(received.0, 15), b"exported memory");
+        write_at_start(received.0, b"imported");
+        assert_eq!(read_at_start(memfd.0, 15), b"imported memory");
+    }
+
+    #[test]
+    fn receive_timeout_is_timed_out() {
+        let _fds = crate::lock_fds();
+        let (_sender, receiver) = socket_pair().unwrap();
+        set_receive_timeout(receiver.0, std::time::Duration::from_millis(10)).unwrap();
+        let err = receive_message(receiver.0).map_err(timed_out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut, "{}", err);
+    }
+}
@@ -0,0 +1,238 @@
+//! Disjoint multi-planar images, with each plane bound to its own memory object
+//! and shared through its own handle, the way video decoders often allocate
+//! their frames.
+//!
+//! The exporter allocates and exports the memory of every plane, the importer
+//! imports the image from all the handles at once. The data is written and
+//! checked through transfers, which address the planes by their aspects.
+
+use gfx_hal as hal;
+use hal::external_memory::{ExternalImageMemoryType, ExternalMemoryProperties, ExternalMemoryType};
+
+use crate::{
+    allocation_failed, check_data, data_check_result, external_image_memory, write_call, write_data,
+    ExportedMemory, ExternalResource, ImportMode, MemoryFilter, Parameters, Resource, RunConfig,
+    SkipReason, TestCase, TestDevice, TestResult, Tests, Verification,
+};
+
+/// OpaqueFd cases for each YCbCr format, on device local memory types and
+/// checked through transfers.
+pub fn disjoint_test_cases() -> Vec<TestCase> {
+    let mut test_cases = Vec::new();
+    #[cfg(unix)]
+    for ycbcr_format in crate::ycbcr::YCBCR_FORMATS.iter() {
+        test_cases.push(TestCase {
+            memory_filter: MemoryFilter::DEVICE_LOCAL,
+            verification: Verification::Transfer,
+            disjoint: true,
+            ..TestCase::new(
+                format!("{} OPAQUE_FD DISJOINT", ycbcr_format.name),
+                Parameters::Image {
+                    external_memory_type: ExternalImageMemoryType::OpaqueFd,
+                    kind: hal::image::Kind::D2(crate::WIDTH, crate::HEIGHT, 1, 1),
+                    mip_levels: 1,
+                    format: ycbcr_format.format,
+                    tiling: hal::image::Tiling::Optimal,
+                    usage: hal::image::Usage::TRANSFER_SRC
+                        | hal::image::Usage::TRANSFER_DST
+                        | hal::image::Usage::SAMPLED,
+                    sparse: hal::memory::SparseFlags::empty(),
+                    view_caps: hal::image::ViewCapabilities::empty(),
+                },
+            )
+        });
+    }
+    test_cases
+}
+
+/// The stages of `crate::run_stages` for a disjoint image: the memory of every
+/// plane is exported, imported, re-exported and re-imported together. Only
+/// imports in the same process are supported.
+pub(crate) fn run_stages<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    memory_types: u32,
+    config: &RunConfig,
+    external_memory_properties: ExternalMemoryProperties,
+    tests: &mut Tests,
+) {
+    let properties = Some(external_memory_properties);
+    let supported = |flag| config.ignore_properties || external_memory_properties.contains(flag);
+    let unsupported = |reason: &str| TestResult::Skipped(SkipReason::Unsupported(reason.into()));
+
+    if !matches!(test_case.parameters, Parameters::Image { .. }) {
+        tests.create_allocate_external_resource = unsupported("Only images can be disjoint");
+        return;
+    }
+    if !device.disjoint_images() {
+        tests.create_allocate_external_resource =
+            unsupported("The device can not bind the planes of an image to separate memory objects");
+        return;
+    }
+    if config.import_mode == ImportMode::CrossProcess {
+        tests.create_allocate_external_resource = unsupported("Disjoint images are only imported in the same process");
+        return;
+    }
+    if !supported(ExternalMemoryProperties::EXPORTABLE) {
+        let skipped = || TestResult::not_supported(external_memory_properties, ExternalMemoryProperties::EXPORTABLE);
+        tests.create_allocate_external_resource = skipped();
+        tests.write_data = skipped();
+        tests.export_memory = skipped();
+        return;
+    }
+    let external_memory_type = test_case.parameters.external_memory_type();
+
+    // Declaration order matters: imported resources must be dropped before the exporter
+    let mut exportable = match create_allocate(device, &test_case.parameters, memory_types) {
+        Ok(resource) => {
+            tests.create_allocate_external_resource = TestResult::Success;
+            resource
+        }
+        Err(err) => {
+            tests.create_allocate_external_resource =
+                allocation_failed("create_allocate_external_disjoint_image", err, memory_types, properties);
+            return;
+        }
+    };
+
+    if let Err(err) = write_data(device, test_case, &mut exportable) {
+        tests.write_data = TestResult::failed(write_call(test_case), err, properties);
+        return;
+    }
+    tests.write_data = TestResult::Success;
+
+    let mut exported = match export_planes(device, external_memory_type, &exportable) {
+        Ok(exported) => {
+            tests.export_memory = TestResult::Success;
+            exported
+        }
+        Err(err) => {
+            tests.export_memory = TestResult::failed("export_memory", err, properties);
+            return;
+        }
+    };
+
+    if !supported(ExternalMemoryProperties::IMPORTABLE) {
+        tests.import_external_resource =
+            TestResult::not_supported(external_memory_properties, ExternalMemoryProperties::IMPORTABLE);
+        return;
+    }
+    let mut imported = match import_planes(device, &test_case.parameters, &mut exported, memory_types) {
+        Ok(resource) => {
+            tests.import_external_resource = TestResult::Success;
+            resource
+        }
+        Err(err) => {
+            tests.import_external_resource =
+                allocation_failed("import_external_disjoint_image", err, memory_types, properties);
+            return;
+        }
+    };
+    let checked = check_data(device, test_case, &mut imported, config.mismatch_dir.as_deref());
+    tests.data_check = data_check_result(test_case, checked, properties);
+
+    if !supported(ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED) {
+        tests.export_from_imported =
+            TestResult::not_supported(external_memory_properties, ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED);
+        return;
+    }
+    let mut reexported = match export_planes(device, external_memory_type, &imported) {
+        Ok(reexported) => {
+            tests.export_from_imported = TestResult::Success;
+            reexported
+        }
+        Err(err) => {
+            tests.export_from_imported = TestResult::failed("export_memory", err, properties);
+            return;
+        }
+    };
+
+    let mut reimported = match import_planes(device, &test_case.parameters, &mut reexported, memory_types) {
+        Ok(resource) => {
+            tests.reimport_external_resource = TestResult::Success;
+            resource
+        }
+        Err(err) => {
+            tests.reimport_external_resource =
+                allocation_failed("import_external_disjoint_image", err, memory_types, properties);
+            return;
+        }
+    };
+    let checked = check_data(device, test_case, &mut reimported, config.mismatch_dir.as_deref());
+    tests.reimport_data_check = data_check_result(test_case, checked, properties);
+}
+
+fn create_allocate<'a, D: TestDevice>(
+    device: &'a D,
+    parameters: &Parameters,
+    memory_types: u32,
+) -> Result<ExternalResource<'a, D>, hal::external_memory::ExternalResourceError> {
+    let (image, memories) = match parameters.clone() {
+        Parameters::Image { external_memory_type, kind, mip_levels, format, tiling, usage, sparse, view_caps } => unsafe {
+            device.create_allocate_external_disjoint_image(
+                external_memory_type,
+                kind,
+                mip_levels,
+                format,
+                tiling,
+                usage,
+                sparse,
+                view_caps,
+                memory_types,
+            )
+        }?,
+        Parameters::Buffer { .. } => unreachable!("Only images can be disjoint"),
+    };
+    Ok(ExternalResource::new_disjoint(device, Resource::Image(image), memories))
+}
+
+/// Exports the memory of each plane of `resource`. The handles exported before
+/// a failure are closed.
+fn export_planes<D: TestDevice>(
+    device: &D,
+    external_memory_type: ExternalMemoryType,
+    resource: &ExternalResource<D>,
+) -> Result<Vec<ExportedMemory>, hal::external_memory::ExternalMemoryExportError> {
+    resource
+        .memories()
+        .iter()
+        .map(|memory| unsafe { device.export_memory(external_memory_type, memory) }.map(ExportedMemory::new))
+        .collect()
+}
+
+/// Imports a disjoint image from the handle of each of its planes, taking their
+/// ownership on success only.
+fn import_planes<'a, D: TestDevice>(
+    device: &'a D,
+    parameters: &Parameters,
+    exported: &mut [ExportedMemory],
+    memory_types: u32,
+) -> Result<ExternalResource<'a, D>, hal::external_memory::ExternalResourceError> {
+    let (image, memories) = match parameters.clone() {
+        Parameters::Image { external_memory_type, kind, mip_levels, format, tiling, usage, sparse, view_caps } => {
+            let external_memories = exported
+                .iter_mut()
+                .map(|exported| external_image_memory(external_memory_type.clone(), exported.take().unwrap(), None))
+                .collect();
+            unsafe {
+                device.import_external_disjoint_image(
+                    external_memories,
+                    kind,
+                    mip_levels,
+                    format,
+                    tiling,
+                    usage,
+                    sparse,
+                    view_caps,
+                    memory_types,
+                )
+            }?
+        }
+        Parameters::Buffer { .. } => unreachable!("Only images can be disjoint"),
+    };
+    // A successful import takes the ownership of the exported handles
+    for exported in exported.iter_mut() {
+        exported.release();
+    }
+    Ok(ExternalResource::new_disjoint(device, Resource::Image(image), memories))
+}
@@ -0,0 +1,582 @@
+//! A `TestDevice` wrapper that makes a chosen call fail on demand and keeps track
+//! of the objects it handed out, used to prove that `run_test` releases everything
+//! on every error path.
+
+use gfx_hal as hal;
+use hal::external_memory::*;
+use std::cell::Cell;
+
+use crate::{run_test, HalError, LiveObjects, RunConfig, TestCase, TestDevice};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    ExternalImageProperties,
+    CreateAllocateExternalBuffer,
+    ImportExternalBuffer,
+    CreateAllocateExternalImage,
+    ImportExternalImage,
+    ExportMemory,
+    MapMemory,
+    FlushMemory,
+    InvalidateMemory,
+    CreateStagingBuffer,
+    /// Any of the copies.
+    Transfer,
+    Dispatch,
+    Render,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 13] = [
+        Operation::ExternalImageProperties,
+        Operation::CreateAllocateExternalBuffer,
+        Operation::ImportExternalBuffer,
+        Operation::CreateAllocateExternalImage,
+        Operation::ImportExternalImage,
+        Operation::ExportMemory,
+        Operation::MapMemory,
+        Operation::FlushMemory,
+        Operation::InvalidateMemory,
+        Operation::CreateStagingBuffer,
+        Operation::Transfer,
+        Operation::Dispatch,
+        Operation::Render,
+    ];
+}
+
+/// Fails the `call`-th invocation (counting from 0) of `operation`.
+pub struct FaultInjectionDevice<'a, D: TestDevice> {
+    device: &'a D,
+    operation: Operation,
+    call: usize,
+    calls: Cell<usize>,
+    triggered: Cell<bool>,
+    live: Cell<LiveObjects>,
+}
+
+impl<'a, D: TestDevice> FaultInjectionDevice<'a, D> {
+    pub fn new(device: &'a D, operation: Operation, call: usize) -> Self {
+        Self {
+            device,
+            operation,
+            call,
+            calls: Cell::new(0),
+            triggered: Cell::new(false),
+            live: Cell::new(LiveObjects::default()),
+        }
+    }
+
+    /// Whether the fault has been injected.
+    pub fn triggered(&self) -> bool {
+        self.triggered.get()
+    }
+
+    /// Objects created through this device and not yet destroyed.
+    pub fn live_objects(&self) -> LiveObjects {
+        self.live.get()
+    }
+
+    fn inject(&self, operation: Operation) -> bool {
+        if operation != self.operation {
+            return false;
+        }
+        let calls = self.calls.get();
+        self.calls.set(calls + 1);
+        if calls == self.call {
+            self.triggered.set(true);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn track(&self, f: impl FnOnce(&mut LiveObjects)) {
+        let mut live = self.live.get();
+        f(&mut live);
+        self.live.set(live);
+    }
+}
+
+impl<'a, D: TestDevice> TestDevice for FaultInjectionDevice<'a, D> {
+    type Buffer = D::Buffer;
+    type Image = D::Image;
+    type Memory = D::Memory;
+
+    fn external_buffer_properties(
+        &self,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        external_memory_type: ExternalBufferMemoryType,
+    ) -> ExternalMemoryProperties {
+        self.device
+            .external_buffer_properties(usage, sparse, external_memory_type)
+    }
+
+    fn external_image_properties(
+        &self,
+        format: hal::format::Format,
+        dimensions: u8,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        view_caps: hal::image::ViewCapabilities,
+        external_memory_type: ExternalMemoryType,
+    ) -> Result<ExternalMemoryProperties, ExternalImagePropertiesError> {
+        if self.inject(Operation::ExternalImageProperties) {
+            return Err(hal::device::OutOfMemory::Host.into());
+        }
+        self.device.external_image_properties(
+            format,
+            dimensions,
+            tiling,
+            usage,
+            view_caps,
+            external_memory_type,
+        )
+    }
+
+    fn adapter_info(&self) -> hal::adapter::AdapterInfo {
+        self.device.adapter_info()
+    }
+
+    fn memory_properties(&self) -> hal::adapter::MemoryProperties {
+        self.device.memory_properties()
+    }
+
+    fn format_properties(&self, format: Option<hal::format::Format>) -> hal::format::Properties {
+        self.device.format_properties(format)
+    }
+
+    fn min_imported_host_pointer_alignment(&self) -> u64 {
+        self.device.min_imported_host_pointer_alignment()
+    }
+
+    fn non_coherent_atom_size(&self) -> u64 {
+        self.device.non_coherent_atom_size()
+    }
+
+    unsafe fn create_allocate_external_buffer(
+        &self,
+        external_memory_type: ExternalBufferMemoryType,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError> {
+        if self.inject(Operation::CreateAllocateExternalBuffer) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self.device.create_allocate_external_buffer(
+            external_memory_type,
+            usage,
+            sparse,
+            type_mask,
+            size,
+        )?;
+        self.track(|live| {
+            live.buffers += 1;
+            live.memories += 1;
+        });
+        Ok(result)
+    }
+
+    unsafe fn import_external_buffer(
+        &self,
+        external_memory: ExternalBufferMemory,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError> {
+        if self.inject(Operation::ImportExternalBuffer) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self
+            .device
+            .import_external_buffer(external_memory, usage, sparse, type_mask, size)?;
+        self.track(|live| {
+            live.buffers += 1;
+            live.memories += 1;
+        });
+        Ok(result)
+    }
+
+    unsafe fn create_allocate_external_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError> {
+        if self.inject(Operation::CreateAllocateExternalImage) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self.device.create_allocate_external_image(
+            external_memory_type,
+            kind,
+            mip_levels,
+            format,
+            tiling,
+            usage,
+            sparse,
+            view_caps,
+            type_mask,
+        )?;
+        self.track(|live| {
+            live.images += 1;
+            live.memories += 1;
+        });
+        Ok(result)
+    }
+
+    unsafe fn import_external_image(
+        &self,
+        external_memory: ExternalImageMemory,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError> {
+        if self.inject(Operation::ImportExternalImage) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self.device.import_external_image(
+            external_memory,
+            kind,
+            mip_levels,
+            format,
+            tiling,
+            usage,
+            sparse,
+            view_caps,
+            type_mask,
+        )?;
+        self.track(|live| {
+            live.images += 1;
+            live.memories += 1;
+        });
+        Ok(result)
+    }
+
+    fn disjoint_images(&self) -> bool {
+        self.device.disjoint_images()
+    }
+
+    unsafe fn create_allocate_external_disjoint_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError> {
+        if self.inject(Operation::CreateAllocateExternalImage) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self.device.create_allocate_external_disjoint_image(
+            external_memory_type,
+            kind,
+            mip_levels,
+            format,
+            tiling,
+            usage,
+            sparse,
+            view_caps,
+            type_mask,
+        )?;
+        self.track(|live| {
+            live.images += 1;
+            live.memories += result.1.len();
+        });
+        Ok(result)
+    }
+
+    unsafe fn import_external_disjoint_image(
+        &self,
+        external_memories: Vec<ExternalImageMemory>,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError> {
+        if self.inject(Operation::ImportExternalImage) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self.device.import_external_disjoint_image(
+            external_memories,
+            kind,
+            mip_levels,
+            format,
+            tiling,
+            usage,
+            sparse,
+            view_caps,
+            type_mask,
+        )?;
+        self.track(|live| {
+            live.images += 1;
+            live.memories += result.1.len();
+        });
+        Ok(result)
+    }
+
+    unsafe fn export_memory(
+        &self,
+        external_memory_type: ExternalMemoryType,
+        memory: &Self::Memory,
+    ) -> Result<PlatformMemory, ExternalMemoryExportError> {
+        if self.inject(Operation::ExportMemory) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        self.device.export_memory(external_memory_type, memory)
+    }
+
+    unsafe fn get_image_subresource_footprint(
+        &self,
+        image: &Self::Image,
+        subresource: hal::image::Subresource,
+    ) -> hal::image::SubresourceFootprint {
+        self.device.get_image_subresource_footprint(image, subresource)
+    }
+
+    unsafe fn drm_format_modifier(&self, image: &Self::Image) -> Option<hal::format::DrmModifier> {
+        self.device.drm_format_modifier(image)
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: &mut Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<*mut u8, hal::device::MapError> {
+        if self.inject(Operation::MapMemory) {
+            return Err(hal::device::MapError::MappingFailed);
+        }
+        self.device.map_memory(memory, segment)
+    }
+
+    unsafe fn unmap_memory(&self, memory: &mut Self::Memory) {
+        self.device.unmap_memory(memory)
+    }
+
+    unsafe fn flush_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory> {
+        if self.inject(Operation::FlushMemory) {
+            return Err(hal::device::OutOfMemory::Device);
+        }
+        self.device.flush_memory(memory, segment)
+    }
+
+    unsafe fn invalidate_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory> {
+        if self.inject(Operation::InvalidateMemory) {
+            return Err(hal::device::OutOfMemory::Device);
+        }
+        self.device.invalidate_memory(memory, segment)
+    }
+
+    unsafe fn create_staging_buffer(&self, size: u64) -> Result<(Self::Buffer, Self::Memory), HalError> {
+        if self.inject(Operation::CreateStagingBuffer) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        let result = self.device.create_staging_buffer(size)?;
+        self.track(|live| {
+            live.buffers += 1;
+            live.memories += 1;
+        });
+        Ok(result)
+    }
+
+    unsafe fn copy_buffer(
+        &self,
+        src: &Self::Buffer,
+        dst: &Self::Buffer,
+        size: u64,
+        external: crate::ExternalBuffer,
+    ) -> Result<(), HalError> {
+        if self.inject(Operation::Transfer) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        self.device.copy_buffer(src, dst, size, external)
+    }
+
+    unsafe fn copy_buffer_to_image(
+        &self,
+        buffer: &Self::Buffer,
+        image: &Self::Image,
+        regions: &[hal::command::BufferImageCopy],
+    ) -> Result<(), HalError> {
+        if self.inject(Operation::Transfer) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        self.device.copy_buffer_to_image(buffer, image, regions)
+    }
+
+    unsafe fn copy_image_to_buffer(
+        &self,
+        image: &Self::Image,
+        buffer: &Self::Buffer,
+        regions: &[hal::command::BufferImageCopy],
+    ) -> Result<(), HalError> {
+        if self.inject(Operation::Transfer) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        self.device.copy_image_to_buffer(image, buffer, regions)
+    }
+
+    unsafe fn dispatch(
+        &self,
+        shader: crate::compute::Shader,
+        buffers: &[&Self::Buffer],
+        push_constants: &[u32],
+        group_count: u32,
+    ) -> Result<(), HalError> {
+        if self.inject(Operation::Dispatch) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        self.device.dispatch(shader, buffers, push_constants, group_count)
+    }
+
+    unsafe fn render(
+        &self,
+        image: &Self::Image,
+        format: hal::format::Format,
+        extent: hal::image::Extent,
+        scene: &crate::render::Scene,
+    ) -> Result<(), HalError> {
+        if self.inject(Operation::Render) {
+            return Err(hal::device::OutOfMemory::Device.into());
+        }
+        self.device.render(image, format, extent, scene)
+    }
+
+    unsafe fn destroy_buffer(&self, buffer: Self::Buffer) {
+        self.track(|live| live.buffers -= 1);
+        self.device.destroy_buffer(buffer)
+    }
+
+    unsafe fn destroy_image(&self, image: Self::Image) {
+        self.track(|live| live.images -= 1);
+        self.device.destroy_image(image)
+    }
+
+    unsafe fn free_memory(&self, memory: Self::Memory) {
+        self.track(|live| live.memories -= 1);
+        self.device.free_memory(memory)
+    }
+
+    fn wait_idle(&self) -> Result<(), hal::device::OutOfMemory> {
+        self.device.wait_idle()
+    }
+}
+
+pub struct FaultInjectionResult {
+    pub name: String,
+    pub operation: Operation,
+    pub call: usize,
+    pub live_objects: LiveObjects,
+    pub leaked_fds: isize,
+}
+
+impl FaultInjectionResult {
+    pub fn leaked(&self) -> bool {
+        !self.live_objects.is_empty() || self.leaked_fds != 0
+    }
+}
+
+impl std::fmt::Debug for FaultInjectionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:?} #{}:", self.name, self.operation, self.call)?;
+        if self.leaked() {
+            write!(
+                f,
+                ":x: leaked {:?}, {} file descriptors",
+                self.live_objects, self.leaked_fds
+            )
+        } else {
+            f.write_str(":heavy_check_mark:")
+        }
+    }
+}
+
+/// Runs every test case once for each call of each operation, failing that call,
+/// and checks that no object or file descriptor survives the test.
+pub fn run_fault_injection_tests<D: TestDevice>(
+    device: &D,
+    test_cases: &[TestCase],
+) -> Vec<FaultInjectionResult> {
+    let mut results = Vec::new();
+    for test_case in test_cases {
+        for &operation in Operation::ALL.iter() {
+            for call in 0.. {
+                let fault_device = FaultInjectionDevice::new(device, operation, call);
+                let fds_before = open_fds();
+                run_test(test_case, &fault_device, &RunConfig::default());
+                let fds_after = open_fds();
+
+                // The operation has been called less than `call` times, go to the next one
+                if !fault_device.triggered() {
+                    break;
+                }
+                results.push(FaultInjectionResult {
+                    name: test_case.name.clone(),
+                    operation,
+                    call,
+                    live_objects: fault_device.live_objects(),
+                    leaked_fds: fds_after - fds_before,
+                });
+            }
+        }
+    }
+    results
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn open_fds() -> isize {
+    std::fs::read_dir("/proc/self/fd")
+        .map(|entries| entries.count() as isize)
+        .unwrap_or(0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn open_fds() -> isize {
+    0
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockDevice;
+
+    #[test]
+    fn nothing_leaks() {
+        let _fds = crate::lock_fds();
+        let device = MockDevice::new();
+        let results = run_fault_injection_tests(&device, &crate::test_cases(&device));
+        assert!(!results.is_empty());
+        for result in &results {
+            assert!(!result.leaked(), "{:?}", result);
+        }
+    }
+}
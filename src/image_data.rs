@@ -0,0 +1,444 @@
+//! Texel patterns written and checked subresource by subresource through the
+//! footprints of an image, so that an importer using a wrong offset or pitch reads
+//! wrong data.
+
+use gfx_hal as hal;
+use hal::format::Aspects;
+use hal::command::BufferImageCopy;
+use hal::image::{Kind, Level, Subresource, SubresourceFootprint};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{ExternalResource, HalError, Parameters, TestDevice};
+
+/// What the exporter writes on an image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ImageContent {
+    /// A pattern covering every byte of every subresource.
+    Pattern,
+    /// `logo.png`, on a single layer and level `Rgba8` image of the same size.
+    Logo,
+    /// The triangle of `render::Scene` on its clear color, on a single layer and
+    /// level `Rgba8` image.
+    Triangle,
+}
+
+impl Default for ImageContent {
+    fn default() -> Self {
+        Self::Pattern
+    }
+}
+
+/// Size of a plane relative to the image, and size of its texels.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaneDesc {
+    pub width_divisor: u32,
+    pub height_divisor: u32,
+    /// In bytes.
+    pub texel_size: u32,
+}
+
+/// A plane of an image, with the aspects its footprints are queried with.
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub aspects: Aspects,
+    pub desc: PlaneDesc,
+}
+
+/// Every texel of an image that gets written and checked.
+#[derive(Debug, Clone)]
+pub struct ImageLayout {
+    pub kind: Kind,
+    pub mip_levels: Level,
+    pub planes: Vec<Plane>,
+}
+
+/// Offsets of the subresources packed in a staging buffer are multiples of it,
+/// which is a multiple of every texel size.
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Aspects of the format planes of a multi-planar image.
+const PLANE_ASPECTS: [Aspects; 3] = [Aspects::PLANE_0, Aspects::PLANE_1, Aspects::PLANE_2];
+
+/// The layout of the image described by `parameters`. Images created with DRM
+/// format modifiers address their planes as memory planes, the other YCbCr images
+/// as format planes and the remaining ones have a single color plane. Memory
+/// planes hold the format planes only for modifiers with as many planes, see
+/// `drm_modifier_test_cases`.
+pub fn image_layout(parameters: &Parameters) -> Option<ImageLayout> {
+    let (kind, mip_levels, format) = match parameters {
+        Parameters::Image { kind, mip_levels, format, .. } => (*kind, *mip_levels, *format),
+        Parameters::Buffer { .. } => return None,
+    };
+    let descs = plane_descs(format);
+    let aspects: &[Aspects] = match (parameters.drm_modifiers(), descs.len()) {
+        (Some(_), _) => &crate::MEMORY_PLANE_ASPECTS,
+        (None, 1) => &[Aspects::COLOR],
+        (None, _) => &PLANE_ASPECTS,
+    };
+    let planes = descs
+        .into_iter()
+        .zip(aspects)
+        .map(|(desc, &aspects)| Plane { aspects, desc })
+        .collect();
+    Some(ImageLayout { kind, mip_levels, planes })
+}
+
+/// The planes of `format`: the ones of YCbCr formats, a single full size plane
+/// otherwise.
+pub fn plane_descs(format: hal::format::Format) -> Vec<PlaneDesc> {
+    match crate::ycbcr::ycbcr_format(format) {
+        Some(ycbcr_format) => ycbcr_format.planes.to_vec(),
+        None => vec![PlaneDesc {
+            width_divisor: 1,
+            height_divisor: 1,
+            texel_size: format.surface_desc().bits as u32 / 8,
+        }],
+    }
+}
+
+/// A subresource of a plane, with the size of the plane at its level.
+struct PlaneSubresource {
+    plane: usize,
+    subresource: Subresource,
+    extent: hal::image::Extent,
+    texel_size: u32,
+}
+
+impl ImageLayout {
+    pub fn aspects(&self) -> Vec<Aspects> {
+        self.planes.iter().map(|plane| plane.aspects).collect()
+    }
+
+    /// Every layer of every level of every plane, in this order.
+    fn subresources(&self) -> Vec<PlaneSubresource> {
+        let mut subresources = Vec::new();
+        for (plane_index, plane) in self.planes.iter().enumerate() {
+            for level in 0..self.mip_levels {
+                let extent = self.kind.level_extent(level);
+                let extent = hal::image::Extent {
+                    width: (extent.width / plane.desc.width_divisor).max(1),
+                    height: (extent.height / plane.desc.height_divisor).max(1),
+                    depth: extent.depth,
+                };
+                for layer in 0..self.kind.num_layers() {
+                    subresources.push(PlaneSubresource {
+                        plane: plane_index,
+                        subresource: Subresource { aspects: plane.aspects, level, layer },
+                        extent,
+                        texel_size: plane.desc.texel_size,
+                    });
+                }
+            }
+        }
+        subresources
+    }
+
+    /// The footprints of the subresources of `image`, in the order of
+    /// `subresources`.
+    pub fn image_footprints<D: TestDevice>(&self, device: &D, image: &D::Image) -> Vec<SubresourceFootprint> {
+        self.subresources()
+            .iter()
+            .map(|subresource| unsafe { device.get_image_subresource_footprint(image, subresource.subresource) })
+            .collect()
+    }
+
+    /// The footprints of the subresources packed one after the other in a
+    /// staging buffer, without padding between the rows, and the size of the
+    /// buffer.
+    pub fn packed_footprints(&self) -> (Vec<SubresourceFootprint>, u64) {
+        let mut offset = 0;
+        let footprints = self
+            .subresources()
+            .iter()
+            .map(|subresource| {
+                let row_pitch = (subresource.extent.width * subresource.texel_size) as u64;
+                let depth_pitch = row_pitch * subresource.extent.height as u64;
+                let array_pitch = depth_pitch * subresource.extent.depth as u64;
+                let footprint = SubresourceFootprint {
+                    slice: offset..offset + array_pitch,
+                    row_pitch,
+                    depth_pitch,
+                    array_pitch,
+                };
+                offset = (offset + array_pitch + STAGING_ALIGNMENT - 1) / STAGING_ALIGNMENT * STAGING_ALIGNMENT;
+                footprint
+            })
+            .collect();
+        (footprints, offset)
+    }
+
+    /// The regions copying each subresource from or to its packed footprint in a
+    /// staging buffer.
+    pub fn copy_regions(&self, packed_footprints: &[SubresourceFootprint]) -> Vec<BufferImageCopy> {
+        self.subresources()
+            .iter()
+            .zip(packed_footprints)
+            .map(|(subresource, footprint)| BufferImageCopy {
+                buffer_offset: footprint.slice.start,
+                buffer_width: subresource.extent.width,
+                buffer_height: subresource.extent.height,
+                image_layers: hal::image::SubresourceLayers {
+                    aspects: subresource.subresource.aspects,
+                    level: subresource.subresource.level,
+                    layers: subresource.subresource.layer..subresource.subresource.layer + 1,
+                },
+                image_offset: hal::image::Offset::ZERO,
+                image_extent: subresource.extent,
+            })
+            .collect()
+    }
+
+    /// Calls `f` with the offset of every byte of every texel of every
+    /// subresource, as laid out by `footprints`, and the position of the byte.
+    /// Stops at the first error.
+    fn for_each_byte(
+        &self,
+        footprints: &[SubresourceFootprint],
+        mut f: impl FnMut(usize, &Texel) -> Result<(), String>,
+    ) -> Result<(), String> {
+        for (subresource, footprint) in self.subresources().iter().zip(footprints) {
+            let Subresource { level, layer, .. } = subresource.subresource;
+            for z in 0..subresource.extent.depth {
+                for y in 0..subresource.extent.height {
+                    for x in 0..subresource.extent.width {
+                        for byte in 0..subresource.texel_size {
+                            let texel = Texel { plane: subresource.plane, level, layer, x, y, z, byte };
+                            f(texel.offset(footprint, subresource.texel_size)?, &texel)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Position of a byte in an image.
+#[derive(Debug)]
+struct Texel {
+    plane: usize,
+    level: Level,
+    layer: hal::image::Layer,
+    x: u32,
+    y: u32,
+    z: u32,
+    byte: u32,
+}
+
+impl Texel {
+    fn offset(&self, footprint: &SubresourceFootprint, texel_size: u32) -> Result<usize, String> {
+        let offset = footprint.slice.start
+            + self.z as u64 * footprint.depth_pitch
+            + self.y as u64 * footprint.row_pitch
+            + (self.x * texel_size + self.byte) as u64;
+        if offset >= footprint.slice.end {
+            return Err(format!("{} is outside of the footprint {:?}", self, footprint));
+        }
+        Ok(offset as usize)
+    }
+
+    /// Neighbouring bytes, texels, rows, slices, layers, levels and planes differ.
+    fn pattern(&self) -> u8 {
+        let mut value = (self.plane as u32).wrapping_mul(0x9e37_79b9)
+            ^ (self.level as u32).wrapping_mul(0x85eb_ca6b)
+            ^ (self.layer as u32).wrapping_mul(0xc2b2_ae35)
+            ^ self.z.wrapping_mul(0x27d4_eb2f)
+            ^ self.y.wrapping_mul(0x1656_67b1)
+            ^ self.x << 2
+            ^ self.byte;
+        value = (value ^ (value >> 16)).wrapping_mul(0x45d9_f3b);
+        value = (value ^ (value >> 16)).wrapping_mul(0x45d9_f3b);
+        (value ^ (value >> 16)) as u8
+    }
+}
+
+impl std::fmt::Display for Texel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Plane {} level {} layer {} texel {},{},{} byte {}",
+            self.plane, self.level, self.layer, self.x, self.y, self.z, self.byte
+        )
+    }
+}
+
+/// The bytes expected on an image.
+enum Source {
+    Pattern,
+    Image(image::RgbaImage),
+}
+
+impl Source {
+    fn new(content: ImageContent, layout: &ImageLayout) -> Result<Self, String> {
+        match content {
+            ImageContent::Pattern => Ok(Self::Pattern),
+            ImageContent::Logo => {
+                let logo = crate::logo::logo();
+                if !is_rgba8_image(layout, Some((logo.width(), logo.height()))) {
+                    return Err(format!(
+                        "The logo is a {}x{} Rgba8 image, the image is {:?} with {} levels",
+                        logo.width(),
+                        logo.height(),
+                        layout.kind,
+                        layout.mip_levels
+                    ));
+                }
+                Ok(Self::Image(logo))
+            }
+            ImageContent::Triangle => {
+                if !is_rgba8_image(layout, None) {
+                    return Err(format!(
+                        "The triangle is drawn on single level Rgba8 images, the image is {:?} with {} levels",
+                        layout.kind, layout.mip_levels
+                    ));
+                }
+                Ok(Self::Image(crate::render::Scene::new(layout.kind.extent()).reference()))
+            }
+        }
+    }
+
+    fn byte(&self, texel: &Texel) -> u8 {
+        match self {
+            Self::Pattern => texel.pattern(),
+            Self::Image(image) => image.get_pixel(texel.x, texel.y)[texel.byte as usize],
+        }
+    }
+}
+
+/// Whether `layout` is a single plane, level and layer 2D image with 4 byte
+/// texels, of `size` if set.
+fn is_rgba8_image(layout: &ImageLayout, size: Option<(u32, u32)>) -> bool {
+    let extent = layout.kind.extent();
+    layout.planes.len() == 1
+        && layout.planes[0].desc.texel_size == 4
+        && layout.mip_levels == 1
+        && layout.kind.num_layers() == 1
+        && extent.depth == 1
+        && size.map_or(true, |size| size == (extent.width, extent.height))
+}
+
+/// Writes `content` on every texel of the image of `resource`, through its
+/// footprints.
+pub fn write_image_data<D: TestDevice>(
+    device: &D,
+    resource: &mut ExternalResource<D>,
+    layout: &ImageLayout,
+    content: ImageContent,
+) -> Result<(), HalError> {
+    let (image, memory) = resource.image_memory_mut()?;
+    let footprints = layout.image_footprints(device, image);
+    write_texels(device, memory, &footprints, layout, content)
+}
+
+/// Checks `content` on every texel of the image of `resource`, through its own
+/// footprints. See `check_texels`.
+pub fn check_image_data<D: TestDevice>(
+    device: &D,
+    resource: &mut ExternalResource<D>,
+    layout: &ImageLayout,
+    content: ImageContent,
+    mismatch_dir: Option<&Path>,
+    name: &str,
+) -> Result<(), HalError> {
+    let (image, memory) = resource.image_memory_mut()?;
+    let footprints = layout.image_footprints(device, image);
+    check_texels(device, memory, &footprints, layout, content, mismatch_dir, name)
+}
+
+/// Writes `content` on every texel of `layout`, at the offsets of `footprints`
+/// in `memory`.
+pub fn write_texels<D: TestDevice>(
+    device: &D,
+    memory: &mut D::Memory,
+    footprints: &[SubresourceFootprint],
+    layout: &ImageLayout,
+    content: ImageContent,
+) -> Result<(), HalError> {
+    let source = Source::new(content, layout)?;
+    with_mapping(device, memory, Access::Write, |mapping| {
+        layout.for_each_byte(footprints, |offset, texel| {
+            unsafe { *mapping.add(offset) = source.byte(texel) };
+            Ok(())
+        })
+    })
+}
+
+/// Checks `content` on every texel of `layout`, at the offsets of `footprints`
+/// in `memory`. Reports the first wrong byte and the number of wrong bytes.
+///
+/// If `content` is an image and `mismatch_dir` is set, the expected image, the
+/// read one and their difference are written there as `<name>.expected.png`,
+/// `<name>.actual.png` and `<name>.diff.png`.
+pub fn check_texels<D: TestDevice>(
+    device: &D,
+    memory: &mut D::Memory,
+    footprints: &[SubresourceFootprint],
+    layout: &ImageLayout,
+    content: ImageContent,
+    mismatch_dir: Option<&Path>,
+    name: &str,
+) -> Result<(), HalError> {
+    let source = Source::new(content, layout)?;
+    let mut actual = match &source {
+        Source::Pattern => None,
+        Source::Image(expected) => Some(image::RgbaImage::new(expected.width(), expected.height())),
+    };
+    let mut wrong_bytes = 0u64;
+    let mut first_wrong_byte = None;
+    with_mapping(device, memory, Access::Read, |mapping| {
+        layout.for_each_byte(footprints, |offset, texel| {
+            let expected = source.byte(texel);
+            let read = unsafe { *mapping.add(offset) };
+            if let Some(actual) = actual.as_mut() {
+                actual.get_pixel_mut(texel.x, texel.y)[texel.byte as usize] = read;
+            }
+            if read != expected {
+                wrong_bytes += 1;
+                if first_wrong_byte.is_none() {
+                    first_wrong_byte = Some(format!("{}: expected {:#04x}, read {:#04x}", texel, expected, read));
+                }
+            }
+            Ok(())
+        })
+    })?;
+
+    let first_wrong_byte = match first_wrong_byte {
+        Some(first_wrong_byte) => first_wrong_byte,
+        None => return Ok(()),
+    };
+    let mut message = format!("{} wrong bytes. {}", wrong_bytes, first_wrong_byte);
+    if let (Source::Image(expected), Some(actual), Some(mismatch_dir)) = (&source, &actual, mismatch_dir) {
+        match crate::logo::write_mismatch_images(mismatch_dir, name, expected, actual) {
+            Ok(()) => message += &format!(" (images written to {})", mismatch_dir.display()),
+            Err(err) => message += &format!(" (failed to write the images: {})", err),
+        }
+    }
+    Err(message.into())
+}
+
+enum Access {
+    Read,
+    Write,
+}
+
+/// Maps the whole `memory` for `f`, invalidating it before reads and flushing it
+/// after writes. The whole memory needs no atom alignment.
+fn with_mapping<D: TestDevice>(
+    device: &D,
+    memory: &mut D::Memory,
+    access: Access,
+    f: impl FnOnce(*mut u8) -> Result<(), String>,
+) -> Result<(), HalError> {
+    let mapping = unsafe { device.map_memory(memory, hal::memory::Segment::ALL) }?;
+    let result = match access {
+        Access::Read => unsafe { device.invalidate_memory(&*memory, hal::memory::Segment::ALL) }
+            .map_err(HalError::from)
+            .and_then(|()| f(mapping).map_err(HalError::from)),
+        Access::Write => f(mapping)
+            .map_err(HalError::from)
+            .and_then(|()| unsafe { device.flush_memory(&*memory, hal::memory::Segment::ALL) }.map_err(HalError::from)),
+    };
+    unsafe { device.unmap_memory(memory) };
+    result
+}
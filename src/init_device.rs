@@ -1,22 +1,41 @@
 use gfx_hal as hal;
-use hal::adapter::{Adapter, PhysicalDevice};
+use hal::adapter::PhysicalDevice;
 use hal::queue::QueueFamily;
 use hal::Instance;
 
-pub fn init_device() -> (
-    gfx_backend_vulkan::Instance,
-    Adapter<gfx_backend_vulkan::Backend>,
-    gfx_backend_vulkan::Device,
-) {
-    let instance: gfx_backend_vulkan::Instance =
-        crate::Instance::create("gfx-rs quad", 1).expect("Failed to create an instance!");
+/// Opens the adapter selected by `adapter` (see `cli::select_adapter`), or the
+/// first one. Fails if no adapter or several match `adapter`.
+pub fn init_device<B: hal::Backend>(adapter: Option<&str>) -> Result<(
+    B::Instance,
+    crate::HalDevice<B>,
+), String> {
+    init_selected_device::<B>(|infos| match adapter {
+        Some(selector) => crate::cli::select_adapter(infos, selector),
+        None => Ok(0),
+    })
+}
+
+/// Opens the adapter whose index `select` picks among the infos of the adapters.
+pub fn init_selected_device<B: hal::Backend>(
+    select: impl FnOnce(&[hal::adapter::AdapterInfo]) -> Result<usize, String>,
+) -> Result<(
+    B::Instance,
+    crate::HalDevice<B>,
+), String> {
+    let instance: B::Instance =
+        B::Instance::create("gfx-rs quad", 1).expect("Failed to create an instance!");
 
     let adapter = {
         let mut adapters = instance.enumerate_adapters();
         for adapter in &adapters {
-            println!("{:?}", adapter.info);
+            log::info!("{:?}", adapter.info);
+        }
+        let infos: Vec<_> = adapters.iter().map(|adapter| adapter.info.clone()).collect();
+        let index = select(&infos)?;
+        if index >= adapters.len() {
+            return Err(String::from("No adapter"));
         }
-        adapters.remove(0)
+        adapters.remove(index)
     };
 
     // Build a new device and associated command queues
@@ -32,7 +51,7 @@ pub fn init_device() -> (
     let sparsely_bound = physical_device
         .features()
         .contains(hal::Features::SPARSE_BINDING | hal::Features::SPARSE_RESIDENCY_IMAGE_2D);
-    let gpu = unsafe {
+    let mut gpu = unsafe {
         physical_device
             .open(
                 &[(family, &[1.0])],
@@ -47,7 +66,8 @@ pub fn init_device() -> (
             .unwrap()
     };
 
-    let device = gpu.device;
+    // The queue group of the graphics family, where the transfers are submitted
+    let queue_group = gpu.queue_groups.pop().unwrap();
 
-    (instance, adapter, device)
+    Ok((instance, crate::HalDevice::new(adapter, gpu.device, queue_group)))
 }
@@ -0,0 +1,1061 @@
+// `back` is defined once per backend feature, and `mock` replaces the backend
+#[cfg(any(
+    all(feature = "vulkan", feature = "dx12"),
+    all(feature = "vulkan", feature = "metal"),
+    all(feature = "vulkan", feature = "gl"),
+    all(feature = "vulkan", feature = "empty"),
+    all(feature = "vulkan", feature = "mock"),
+    all(feature = "dx12", feature = "metal"),
+    all(feature = "dx12", feature = "gl"),
+    all(feature = "dx12", feature = "empty"),
+    all(feature = "dx12", feature = "mock"),
+    all(feature = "metal", feature = "gl"),
+    all(feature = "metal", feature = "empty"),
+    all(feature = "metal", feature = "mock"),
+    all(feature = "gl", feature = "empty"),
+    all(feature = "gl", feature = "mock"),
+    all(feature = "empty", feature = "mock"),
+))]
+compile_error!(
+    "Enable a single backend feature, e.g. `--no-default-features --features mock` for the mock backend"
+);
+
+#[cfg(feature = "vulkan")]
+pub extern crate gfx_backend_vulkan as back;
+#[cfg(feature = "dx12")]
+pub extern crate gfx_backend_dx12 as back;
+#[cfg(feature = "metal")]
+pub extern crate gfx_backend_metal as back;
+#[cfg(feature = "gl")]
+pub extern crate gfx_backend_gl as back;
+#[cfg(feature = "empty")]
+pub extern crate gfx_backend_empty as back;
+
+pub mod init_device;
+
+mod common;
+pub use common::*;
+
+mod test_device;
+pub use test_device::*;
+
+mod resource;
+pub use resource::*;
+
+mod test_result;
+pub use test_result::*;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+
+pub mod fault_injection;
+
+#[cfg(unix)]
+pub mod cross_process;
+
+pub mod report;
+
+pub mod case_file;
+
+pub mod matrix;
+
+pub mod consistency;
+
+pub mod capabilities;
+
+pub mod image_data;
+
+pub mod logo;
+
+pub mod payload;
+
+pub mod transfer;
+
+pub mod compute;
+
+pub mod render;
+
+pub mod ycbcr;
+
+pub mod disjoint;
+
+pub mod cli;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use gfx_hal as hal;
+use hal::format::{AsFormat,DrmModifier,Aspects,ImageFeature};
+use std::convert::TryInto;
+use hal::image::{Subresource,SubresourceFootprint,DrmFormatImageProperties};
+use hal::external_memory::*;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 600;
+
+/// Held by the tests opening file descriptors, since the fault injection tests
+/// count the open file descriptors of the whole process.
+#[cfg(test)]
+static FD_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+fn lock_fds() -> std::sync::MutexGuard<'static, ()> {
+    FD_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// The payload of a buffer. See `payload`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataTest {
+    /// In bytes. Defaults to the size of the file of a `Pattern::File` and to
+    /// `payload::DEFAULT_SIZE` otherwise.
+    pub size: Option<u64>,
+    pub pattern: payload::Pattern,
+}
+
+/// Where the exported memory gets imported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// By the same device that exported it.
+    SameProcess,
+    /// By a new device created in the `import_helper` process.
+    CrossProcess,
+}
+
+/// How `run_test` runs the stages of a test case.
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    pub import_mode: ImportMode,
+    /// Run every stage, even the ones the queried external memory properties do
+    /// not claim to support.
+    pub ignore_properties: bool,
+    /// Where the expected, read and difference images of a mismatching image
+    /// content are written.
+    pub mismatch_dir: Option<std::path::PathBuf>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            import_mode: ImportMode::SameProcess,
+            ignore_properties: false,
+            mismatch_dir: None,
+        }
+    }
+}
+
+pub fn run_tests<D: TestDevice>(
+    device: &D,
+    test_cases: &[TestCase],
+    config: &RunConfig,
+) -> Vec<Tests> {
+    test_cases
+        .iter()
+        .flat_map(|test_case| run_test(test_case, device, config))
+        .collect()
+}
+
+/// Every buffer and image test case supported on this platform.
+pub fn test_cases<D: TestDevice>(device: &D) -> Vec<TestCase> {
+    let mut test_cases = buffer_test_cases();
+    test_cases.extend(image_test_cases(device));
+    test_cases.extend(non_coherent_test_cases());
+    test_cases.extend(transfer::transfer_test_cases());
+    test_cases.extend(compute::compute_test_cases());
+    test_cases.extend(render::render_test_cases());
+    test_cases
+}
+
+/// The buffer and `Rgba8Srgb` image cases, on non-coherent memory types only.
+pub fn non_coherent_test_cases() -> Vec<TestCase> {
+    buffer_test_cases()
+        .into_iter()
+        .chain(image_parameters_test_cases(hal::format::Rgba8Srgb::SELF))
+        .map(|test_case| TestCase {
+            name: format!("{} NON_COHERENT", test_case.name),
+            memory_filter: MemoryFilter::NON_COHERENT,
+            ..test_case
+        })
+        .collect()
+}
+
+pub fn buffer_test_cases() -> Vec<TestCase> {
+    let mut test_cases = Vec::new();
+    #[cfg(any(unix))]
+    {
+        test_cases.push(TestCase::new(
+            "OPAQUE_FD",
+            Parameters::Buffer {
+                external_memory_type: hal::external_memory::ExternalBufferMemoryType::OpaqueFd,
+                buffer_usage: hal::buffer::Usage::VERTEX,
+                buffer_flags: hal::memory::SparseFlags::empty()
+            },
+        ));
+        test_cases.push(TestCase::new(
+            "DMA_BUF",
+            Parameters::Buffer {
+                external_memory_type: hal::external_memory::ExternalBufferMemoryType::DmaBuf,
+                buffer_usage: hal::buffer::Usage::VERTEX,
+                buffer_flags: hal::memory::SparseFlags::empty()
+            },
+        ));
+    }
+
+    test_cases.push(TestCase::new(
+        "HOST_ALLOCATION",
+        Parameters::Buffer {
+            external_memory_type: hal::external_memory::ExternalBufferMemoryType::HostAllocation,
+            buffer_usage: hal::buffer::Usage::VERTEX,
+            buffer_flags: hal::memory::SparseFlags::empty()
+        },
+    ));
+
+    test_cases.push(TestCase::new(
+        "HOST_MAPPED_FOREIGN_MEMORY",
+        Parameters::Buffer {
+            external_memory_type: hal::external_memory::ExternalBufferMemoryType::HostMappedForeignMemory,
+            buffer_usage: hal::buffer::Usage::VERTEX,
+            buffer_flags: hal::memory::SparseFlags::empty()
+        },
+    ));
+    test_cases
+}
+
+pub fn image_test_cases<D: TestDevice>(device: &D) -> Vec<TestCase> {
+    let mut test_cases = image_parameters_test_cases(hal::format::Rgba8Srgb::SELF);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    test_cases.extend(drm_modifier_test_cases(device, &matrix::FORMATS));
+    test_cases.extend(ycbcr::ycbcr_test_cases(device));
+    test_cases.extend(disjoint::disjoint_test_cases());
+    test_cases.extend(logo::logo_test_cases());
+    test_cases
+}
+
+fn image_parameters(external_memory_type: ExternalImageMemoryType, format: hal::format::Format) -> Parameters {
+    Parameters::Image {
+        external_memory_type,
+        kind: hal::image::Kind::D2(WIDTH as hal::image::Size, HEIGHT as hal::image::Size, 1, 1),
+        mip_levels: 1,
+        format,
+        tiling: hal::image::Tiling::Linear,
+        usage: hal::image::Usage::TRANSFER_DST | hal::image::Usage::SAMPLED,
+        sparse: hal::memory::SparseFlags::empty(),
+        view_caps: hal::image::ViewCapabilities::empty(),
+    }
+}
+
+fn image_parameters_test_cases(format: hal::format::Format) -> Vec<TestCase> {
+    let mut test_cases = Vec::new();
+    #[cfg(any(unix))]
+    {
+        test_cases.push(TestCase::new(
+            "OPAQUE_FD",
+            image_parameters(hal::external_memory::ExternalImageMemoryType::OpaqueFd, format),
+        ));
+        test_cases.push(TestCase::new(
+            "DMA_BUF",
+            image_parameters(hal::external_memory::ExternalImageMemoryType::DmaBuf(Vec::new()), format),
+        ));
+    }
+
+    test_cases.push(TestCase::new(
+        "HOST_ALLOCATION",
+        image_parameters(hal::external_memory::ExternalImageMemoryType::HostAllocation, format),
+    ));
+
+    test_cases.push(TestCase::new(
+        "HOST_MAPPED_FOREIGN_MEMORY",
+        image_parameters(hal::external_memory::ExternalImageMemoryType::HostMappedForeignMemory, format),
+    ));
+    test_cases
+}
+
+/// One DmaBuf test case for each DRM format modifier of each format. The name
+/// reports the plane count and the valid usages of the modifier. The image
+/// keeps the usages of the other cases that the modifier allows, and modifiers
+/// allowing none of them are skipped. The data of modifiers whose memory
+/// planes are not the planes of the format is not checked.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn drm_modifier_test_cases<D: TestDevice>(device: &D, formats: &[hal::format::Format]) -> Vec<TestCase> {
+    let mut test_cases = Vec::new();
+    for &format in formats {
+        for drm_format_properties in device.format_properties(Some(format)).drm_format_properties {
+            let mut parameters = image_parameters(
+                ExternalImageMemoryType::DmaBuf(vec![drm_format_properties.drm_modifier]),
+                format,
+            );
+            if let Parameters::Image { ref mut usage, .. } = parameters {
+                *usage &= drm_modifier_usage(drm_format_properties.valid_usages);
+                if usage.is_empty() {
+                    debug!(
+                        "Skipping {:?} {:?}: none of the usages of the test cases is valid ({:?})",
+                        format, drm_format_properties.drm_modifier, drm_format_properties.valid_usages
+                    );
+                    continue;
+                }
+            }
+            // The data is addressed through the memory planes, which are the format
+            // planes only if there are as many; the others hold metadata such as
+            // compression state
+            let plane_count = drm_format_properties.plane_count as usize;
+            let verification = if plane_count == image_data::plane_descs(format).len() {
+                Verification::Map
+            } else {
+                Verification::Unchecked
+            };
+            test_cases.push(TestCase {
+                verification,
+                ..TestCase::new(
+                    format!(
+                        "DMA_BUF {:?} {:?} ({} planes, {:?})",
+                        format,
+                        drm_format_properties.drm_modifier,
+                        drm_format_properties.plane_count,
+                        drm_format_properties.valid_usages
+                    ),
+                    parameters,
+                )
+            });
+        }
+    }
+    test_cases
+}
+
+/// The image usages allowed by the `valid_usages` of a DRM format modifier.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn drm_modifier_usage(valid_usages: ImageFeature) -> hal::image::Usage {
+    [
+        (ImageFeature::TRANSFER_SRC, hal::image::Usage::TRANSFER_SRC),
+        (ImageFeature::TRANSFER_DST, hal::image::Usage::TRANSFER_DST),
+        (ImageFeature::SAMPLED, hal::image::Usage::SAMPLED),
+        (ImageFeature::STORAGE, hal::image::Usage::STORAGE),
+        (ImageFeature::COLOR_ATTACHMENT, hal::image::Usage::COLOR_ATTACHMENT),
+        (ImageFeature::DEPTH_STENCIL_ATTACHMENT, hal::image::Usage::DEPTH_STENCIL_ATTACHMENT),
+    ]
+    .iter()
+    .filter(|(feature, _)| valid_usages.contains(*feature))
+    .fold(hal::image::Usage::empty(), |usage, &(_, feature_usage)| usage | feature_usage)
+}
+
+/// Runs `test_case` once on each memory type matching its memory filter and
+/// verification.
+pub fn run_test<D: TestDevice>(
+    test_case: &TestCase,
+
+    device: &D,
+
+    config: &RunConfig,
+) -> Vec<Tests> {
+    // The seed is picked before running, so that the exporter and the importer agree on it
+    let test_case = &TestCase { data: test_case.data.with_seed(), ..test_case.clone() };
+    let new_tests = |name: String| {
+        let mut tests = Tests::new(name, test_case.parameters.clone());
+        if let Parameters::Buffer{..} = test_case.parameters {
+            tests.seed = test_case.data.seed();
+        }
+        tests
+    };
+
+    let memory_types = memory_types(device, &test_case.memory_filter, test_case.verification);
+    if memory_types.is_empty() {
+        let mut tests = new_tests(test_case.name.clone());
+        tests.create_allocate_external_resource = TestResult::Skipped(SkipReason::Unsupported(
+            format!("No memory type matches {:?} with {:?} verification", test_case.memory_filter, test_case.verification)
+        ));
+        return vec![tests];
+    }
+
+    memory_types.into_iter().map(|memory_type| {
+        let mut tests = new_tests(format!("{} (memory type {})", test_case.name, memory_type.index));
+
+        run_stages(device, test_case, 1 << memory_type.index, config, &mut tests);
+        tests.memory_type = Some(memory_type);
+
+        if let Err(err) = device.wait_idle() {
+            error!("Error on `wait_idle`: {}", err);
+        }
+        tests
+    }).collect()
+}
+
+fn run_stages<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    memory_types: u32,
+    config: &RunConfig,
+    tests: &mut Tests,
+) {
+    let parameters = test_case.parameters.clone();
+    let external_memory_properties = match parameters.clone() {
+        Parameters::Buffer{external_memory_type,buffer_usage,buffer_flags}=>{
+            device
+            .external_buffer_properties(buffer_usage, buffer_flags, external_memory_type)
+        }
+        Parameters::Image{external_memory_type, kind,mip_levels: _,format,tiling,usage,sparse: _,view_caps}=>{
+            match device
+            .external_image_properties(format,matrix::kind_dimensions(kind),tiling,usage,view_caps, external_memory_type.external_memory_type())
+            {
+                Ok(external_memory_properties)=>external_memory_properties,
+                Err(err)=>{
+                    tests.create_allocate_external_resource = TestResult::failed("external_image_properties", err, None);
+                    return;
+                }
+            }
+        }
+    };
+    let properties = Some(external_memory_properties);
+    tests.properties = properties;
+    let supported = |flag| config.ignore_properties || external_memory_properties.contains(flag);
+
+    info!("{:?}",&external_memory_properties);
+
+    // Buffer allocations, sized after the payload
+    let data_len = match &parameters {
+        Parameters::Buffer{..} => match test_case.data.payload_size() {
+            Ok(data_len) => data_len.max(1),
+            Err(err) => {
+                tests.create_allocate_external_resource = TestResult::failed("payload_size", err, properties);
+                return;
+            }
+        },
+        Parameters::Image{..} => 1,
+    };
+    // A `--pattern` or `--payload-size` applied to every case may not suit the compute shaders
+    if test_case.verification == Verification::Compute {
+        let len = test_case.data.payload_size().unwrap_or_default();
+        if let Some(reason) = compute::unsupported_payload(&test_case.data, len) {
+            tests.create_allocate_external_resource = TestResult::Skipped(SkipReason::Unsupported(reason));
+            return;
+        }
+    }
+    if test_case.disjoint {
+        disjoint::run_stages(device, test_case, memory_types, config, external_memory_properties, tests);
+        return;
+    }
+    // Flushed and invalidated ranges are rounded up to the atom size, so the buffer must cover them
+    let non_coherent_alignment = device.non_coherent_atom_size();
+    let host_ptr_alignment = device.min_imported_host_pointer_alignment();
+    let alignment = host_ptr_alignment.max(non_coherent_alignment).max(1);
+
+    let padded_buffer_len =
+        ((data_len + alignment - 1) / alignment) * alignment;
+
+    let external_memory_type = parameters.external_memory_type();
+
+    // Declaration order matters: imported resources must be dropped before the exporter
+    let mut exportable = None;
+    let mut exported = None;
+    let mut imported = None;
+    let mut reexported = None;
+
+    if supported(ExternalMemoryProperties::EXPORTABLE){
+        let mut resource = match parameters.clone() {
+            Parameters::Buffer{external_memory_type,buffer_usage,buffer_flags}=>{
+                let (buffer, memory) = match unsafe {
+                    device.create_allocate_external_buffer(
+                        external_memory_type,
+                        buffer_usage,
+                        buffer_flags,
+                        memory_types,
+                        padded_buffer_len,
+                    )
+                } {
+                    Ok(buffer_memory) => {
+                        tests.create_allocate_external_resource = TestResult::Success;
+                        buffer_memory
+                    }
+                    Err(err) => {
+                        tests.create_allocate_external_resource = allocation_failed("create_allocate_external_buffer", err, memory_types, properties);
+                        return;
+                    }
+                };
+                ExternalResource::new(device, Resource::Buffer(buffer), memory)
+            }
+            Parameters::Image{external_memory_type,kind,mip_levels,format,tiling,usage,sparse,view_caps}=>{
+                let (image, memory) = match unsafe {
+                    device.create_allocate_external_image(
+                        external_memory_type,
+                        kind,mip_levels,format,tiling,usage,sparse,view_caps,
+                        memory_types
+                    )
+                } {
+                    Ok(image_memory) => {
+                        tests.create_allocate_external_resource = TestResult::Success;
+                        image_memory
+                    }
+                    Err(err) => {
+                        tests.create_allocate_external_resource = allocation_failed("create_allocate_external_image", err, memory_types, properties);
+                        return;
+                    }
+                };
+                ExternalResource::new(device, Resource::Image(image), memory)
+            }
+        };
+
+        if let Err(err) = write_data(device, test_case, &mut resource) {
+            tests.write_data = TestResult::failed(write_call(test_case), err, properties);
+            return;
+        }
+        tests.write_data = match test_case.verification {
+            Verification::Unchecked => unchecked(),
+            _ => TestResult::Success,
+        };
+
+        let external_memory = match export_resource(device, external_memory_type, &mut resource) {
+            Ok(external_memory) => {
+                tests.export_memory = TestResult::Success;
+                external_memory
+            }
+            Err((call, err)) => {
+                tests.export_memory = TestResult::failed(call, err, properties);
+                return;
+            }
+        };
+
+        exportable = Some(resource);
+        exported = Some(ExportedMemory::new(external_memory));
+    } else {
+        let skipped = || TestResult::not_supported(external_memory_properties, ExternalMemoryProperties::EXPORTABLE);
+        tests.create_allocate_external_resource = skipped();
+        tests.write_data = skipped();
+        tests.export_memory = skipped();
+    }
+
+    if !supported(ExternalMemoryProperties::IMPORTABLE) {
+        tests.import_external_resource = TestResult::not_supported(external_memory_properties, ExternalMemoryProperties::IMPORTABLE);
+    } else if let Some(exported) = exported.as_mut() {
+        let drm_properties = match &parameters {
+            #[cfg(any(target_os = "linux", target_os = "android", doc))]
+            Parameters::Image{external_memory_type: ExternalImageMemoryType::DmaBuf(drm_modifiers),format,..} if !drm_modifiers.is_empty() => {
+                let exportable_image = match exportable.as_ref().unwrap().image() {
+                    Ok(image)=>image,
+                    Err(err)=>{tests.import_external_resource = TestResult::failed("import_external_image", err, properties);return;}
+                };
+                match drm_import_properties(device, *format, exportable_image) {
+                    Ok(drm_properties)=>Some(drm_properties),
+                    Err(err)=>{
+                        tests.import_external_resource = TestResult::failed("drm_format_modifier", err, properties);
+                        return;
+                    }
+                }
+            }
+            _=>None
+        };
+
+        match config.import_mode {
+            ImportMode::SameProcess => {
+                if let Some(drm_properties) = &drm_properties {
+                    tests.wrong_drm_modifier_import = wrong_drm_modifier_import(device, &parameters, exportable.as_ref().unwrap(), drm_properties, memory_types, properties);
+                }
+
+                // Memory planes of DRM format modifiers, the planes of the image layout otherwise
+                let plane_aspects = match (&drm_properties, image_data::image_layout(&parameters)) {
+                    (Some(drm_properties), _) => MEMORY_PLANE_ASPECTS[..drm_properties.plane_layouts.len()].to_vec(),
+                    (None, Some(layout)) => layout.aspects(),
+                    (None, None) => Vec::new(),
+                };
+
+                let mut resource = match import_resource(device, &parameters, exported.take().unwrap(), drm_properties, memory_types, padded_buffer_len) {
+                    Ok(resource) => {
+                        tests.import_external_resource = TestResult::Success;
+                        resource
+                    }
+                    Err(err) => {
+                        tests.import_external_resource = allocation_failed(import_call(&parameters), err, memory_types, properties);
+                        return;
+                    }
+                };
+                // A successful import takes the ownership of the exported handle
+                exported.release();
+
+                // Optimally tiled images have no footprints
+                if matches!(parameters, Parameters::Image{tiling,..} if tiling != hal::image::Tiling::Optimal) {
+                    tests.footprint_check = footprint_check(device, exportable.as_ref().unwrap(), &resource, &plane_aspects, properties);
+                }
+
+                let checked = check_data(device, test_case, &mut resource, config.mismatch_dir.as_deref());
+                tests.data_check = data_check_result(test_case, checked, properties);
+                imported = Some(resource);
+            }
+            #[cfg(unix)]
+            ImportMode::CrossProcess => {
+                if drm_properties.is_some() {
+                    tests.wrong_drm_modifier_import = TestResult::Skipped(SkipReason::Unsupported(
+                        "Only in the same process".into()
+                    ));
+                }
+                let fd = match exported.fd() {
+                    Some(fd)=>fd,
+                    None=>{
+                        tests.import_external_resource = TestResult::Skipped(SkipReason::Unsupported(
+                            format!("{:?} memory can not be shared with another process", external_memory_type)
+                        ));
+                        return;
+                    }
+                };
+                let request = cross_process::ImportRequest {
+                    test_case: test_case.clone(),
+                    size: padded_buffer_len,
+                    drm_properties: drm_properties.map(Into::into),
+                    mismatch_dir: config.mismatch_dir.clone(),
+                    memory_types,
+                };
+                match cross_process::import_in_helper(&request, fd, &device.adapter_info().into()) {
+                    Ok(response) => {
+                        tests.import_external_resource = match response.import_external_resource {
+                            Ok(()) => TestResult::Success,
+                            Err(err) => TestResult::failed(import_call(&parameters), err, properties),
+                        };
+                        if let Some(data_check) = response.data_check {
+                            tests.data_check = data_check_result(test_case, data_check, properties);
+                        }
+                    }
+                    Err(err) => {
+                        tests.import_external_resource = TestResult::failed("import_in_helper", err.to_string(), properties);
+                    }
+                }
+                tests.export_from_imported = TestResult::Skipped(SkipReason::Unsupported(
+                    "The imported memory belongs to the helper process".into()
+                ));
+                return;
+            }
+            #[cfg(not(unix))]
+            ImportMode::CrossProcess => {
+                tests.import_external_resource = TestResult::Skipped(SkipReason::Unsupported(
+                    "Cross process import is only supported on unix".into()
+                ));
+                return;
+            }
+        }
+    }
+
+
+    if !supported(ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED) {
+        tests.export_from_imported = TestResult::not_supported(external_memory_properties, ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED);
+    } else if let Some(imported) = imported.as_mut() {
+        match export_resource(device, external_memory_type, imported) {
+            Ok(external_memory) => {
+                tests.export_from_imported = TestResult::Success;
+                reexported = Some(ExportedMemory::new(external_memory));
+            }
+            Err((call, err)) => {
+                tests.export_from_imported = TestResult::failed(call, err, properties);
+                return;
+            }
+        }
+    }
+
+    // Import the re-exported handle, closing the export -> import -> re-export -> re-import chain
+    if let (Some(imported), Some(reexported)) = (imported.as_ref(), reexported.as_mut()) {
+        let drm_properties = match &parameters {
+            #[cfg(any(target_os = "linux", target_os = "android", doc))]
+            Parameters::Image{external_memory_type: ExternalImageMemoryType::DmaBuf(drm_modifiers),format,..} if !drm_modifiers.is_empty() => {
+                let drm_properties = imported.image()
+                    .map_err(HalError::from)
+                    .and_then(|image| drm_import_properties(device, *format, image).map_err(HalError::from));
+                match drm_properties {
+                    Ok(drm_properties)=>Some(drm_properties),
+                    Err(err)=>{
+                        tests.reimport_external_resource = TestResult::failed("drm_format_modifier", err, properties);
+                        return;
+                    }
+                }
+            }
+            _=>None
+        };
+        #[cfg(not(any(target_os = "linux", target_os = "android", doc)))]
+        let _ = imported;
+
+        let mut resource = match import_resource(device, &parameters, reexported.take().unwrap(), drm_properties, memory_types, padded_buffer_len) {
+            Ok(resource) => {
+                tests.reimport_external_resource = TestResult::Success;
+                resource
+            }
+            Err(err) => {
+                tests.reimport_external_resource = allocation_failed(import_call(&parameters), err, memory_types, properties);
+                return;
+            }
+        };
+        reexported.release();
+
+        // The re-imported resource is dropped at the end of this block, before `imported`
+        let checked = check_data(device, test_case, &mut resource, config.mismatch_dir.as_deref());
+        tests.reimport_data_check = data_check_result(test_case, checked, properties);
+    }
+}
+
+/// Writes the data of `test_case`: its image content on images, its payload on
+/// buffers.
+fn write_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &mut ExternalResource<D>,
+) -> Result<(), HalError> {
+    match test_case.verification {
+        Verification::Map => {}
+        Verification::Transfer => return transfer::write_data(device, test_case, resource),
+        Verification::Compute => return compute::write_data(device, test_case, resource),
+        Verification::Render => return render::write_data(device, test_case, resource),
+        Verification::Unchecked => return Ok(()),
+    }
+    match image_data::image_layout(&test_case.parameters) {
+        Some(layout) => image_data::write_image_data(device, resource, &layout, test_case.image_content),
+        None => {
+            let len = test_case.data.payload_size()?;
+            write_memory(device, resource.memory_mut().unwrap(), &mut test_case.data.reader()?, len)
+        }
+    }
+}
+
+/// Name of the call that writes the data of `test_case`.
+fn write_call(test_case: &TestCase) -> &'static str {
+    match (test_case.verification, &test_case.parameters) {
+        (Verification::Map, _) => "write_memory",
+        (Verification::Transfer, Parameters::Buffer { .. }) => "copy_buffer",
+        (Verification::Transfer, Parameters::Image { .. }) => "copy_buffer_to_image",
+        (Verification::Compute, _) => "dispatch",
+        (Verification::Render, _) => "render",
+        (Verification::Unchecked, _) => "write_data",
+    }
+}
+
+/// The result of the stages writing and checking the data of an `Unchecked`
+/// test case, see `Verification::Unchecked`.
+fn unchecked() -> TestResult {
+    TestResult::Skipped(SkipReason::Unsupported(
+        "The data of the image can not be written nor checked".into(),
+    ))
+}
+
+/// The result of the data check of `test_case`, skipped if its verification
+/// checks nothing.
+fn data_check_result(
+    test_case: &TestCase,
+    checked: Result<(), impl Into<HalError>>,
+    properties: Option<ExternalMemoryProperties>,
+) -> TestResult {
+    match (test_case.verification, checked) {
+        (Verification::Unchecked, _) => unchecked(),
+        (_, Ok(())) => TestResult::Success,
+        (_, Err(err)) => TestResult::failed("read_memory", err, properties),
+    }
+}
+
+/// Checks the data written by the exporter of `test_case`. See
+/// `image_data::check_image_data` for `mismatch_dir`.
+pub fn check_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &mut ExternalResource<D>,
+    mismatch_dir: Option<&std::path::Path>,
+) -> Result<(), HalError> {
+    match test_case.verification {
+        Verification::Map => {}
+        Verification::Transfer | Verification::Render => {
+            return transfer::check_data(device, test_case, resource, mismatch_dir)
+        }
+        Verification::Compute => return compute::check_data(device, test_case, resource),
+        // Reported as skipped, see `data_check_result`
+        Verification::Unchecked => return Ok(()),
+    }
+    match image_data::image_layout(&test_case.parameters) {
+        Some(layout) => image_data::check_image_data(
+            device,
+            resource,
+            &layout,
+            test_case.image_content,
+            mismatch_dir,
+            &test_case.name,
+        ),
+        None => {
+            let len = test_case.data.payload_size()?;
+            check_memory(device, resource.memory_mut().unwrap(), &mut test_case.data.reader()?, len)
+        }
+    }
+}
+
+/// Imports the memory of `exportable` with a DRM format modifier other than the one
+/// it uses, which must fail without leaking the exported handle.
+fn wrong_drm_modifier_import<D: TestDevice>(
+    device: &D,
+    parameters: &Parameters,
+    exportable: &ExternalResource<D>,
+    drm_properties: &DrmFormatImageProperties,
+    memory_types: u32,
+    properties: Option<ExternalMemoryProperties>,
+) -> TestResult {
+    let format = match parameters {
+        Parameters::Image{format,..}=>*format,
+        Parameters::Buffer{..}=>return TestResult::Skipped(SkipReason::Unsupported("Only for images".into())),
+    };
+    let wrong_modifier = device
+        .format_properties(Some(format))
+        .drm_format_properties
+        .iter()
+        .map(|drm_format_properties| drm_format_properties.drm_modifier)
+        .find(|&drm_modifier| drm_modifier != drm_properties.drm_modifier);
+    let wrong_modifier = match wrong_modifier {
+        Some(wrong_modifier)=>wrong_modifier,
+        None=>return TestResult::Skipped(SkipReason::Unsupported(format!("{:?} has a single DRM format modifier", format))),
+    };
+
+    let mut exported = match unsafe { device.export_memory(parameters.external_memory_type(), exportable.memory()) } {
+        Ok(external_memory)=>ExportedMemory::new(external_memory),
+        Err(err)=>return TestResult::failed("export_memory", err, properties),
+    };
+    let wrong_properties = DrmFormatImageProperties {
+        drm_modifier: wrong_modifier,
+        plane_layouts: drm_properties.plane_layouts.clone(),
+    };
+    match import_resource(device, parameters, exported.take().unwrap(), Some(wrong_properties), memory_types, 0) {
+        Ok(_resource) => {
+            exported.release();
+            TestResult::failed(
+                import_call(parameters),
+                format!("The import with the modifier {:?} instead of {:?} succeeded", wrong_modifier, drm_properties.drm_modifier),
+                properties,
+            )
+        }
+        Err(err) => {
+            info!("Import with a wrong DRM format modifier failed as expected: {}", err);
+            TestResult::Success
+        }
+    }
+}
+
+/// Exports the memory of `resource`: host allocations are exported by mapping
+/// them, the other types through `export_memory`.
+fn export_resource<D: TestDevice>(
+    device: &D,
+    external_memory_type: ExternalMemoryType,
+    resource: &mut ExternalResource<D>,
+) -> Result<PlatformMemory, (&'static str, HalError)> {
+    if external_memory_type == ExternalMemoryType::HostAllocation
+        || external_memory_type == ExternalMemoryType::HostMappedForeignMemory
+    {
+        let ptr: Ptr = resource.map().map_err(|err| ("map_memory", err.into()))?.into();
+        Ok(PlatformMemory::Ptr(ptr))
+    } else {
+        unsafe { device.export_memory(external_memory_type, resource.memory()) }
+            .map_err(|err| ("export_memory", err.into()))
+    }
+}
+
+/// The result of a `call` allocating or importing a resource on the memory type
+/// of `memory_types` that failed with `err`. The memory type is picked from the
+/// memory properties alone, so one the resource does not allow is skipped rather
+/// than failed.
+fn allocation_failed(
+    call: &'static str,
+    err: ExternalResourceError,
+    memory_types: u32,
+    properties: Option<ExternalMemoryProperties>,
+) -> TestResult {
+    match err {
+        ExternalResourceError::NoValidMemoryTypeId => TestResult::Skipped(SkipReason::Unsupported(format!(
+            "memory type {} not allowed for this resource",
+            memory_types.trailing_zeros()
+        ))),
+        err => TestResult::failed(call, err, properties),
+    }
+}
+
+/// Name of the hal call that imports the resource described by `parameters`.
+fn import_call(parameters: &Parameters) -> &'static str {
+    match parameters {
+        Parameters::Buffer{..}=>"import_external_buffer",
+        Parameters::Image{..}=>"import_external_image",
+    }
+}
+
+/// The memory types matching `filter` that `verification` can use, with their heap.
+pub fn memory_types<D: TestDevice>(
+    device: &D,
+    filter: &MemoryFilter,
+    verification: Verification,
+) -> Vec<MemoryTypeInfo> {
+    let memory_properties = device.memory_properties();
+    memory_properties
+        .memory_types
+        .iter()
+        .enumerate()
+        .filter(|(_, memory_type)| filter.matches(memory_type.properties, verification))
+        .map(|(index, memory_type)| {
+            let heap = &memory_properties.memory_heaps[memory_type.heap_index];
+            MemoryTypeInfo {
+                index,
+                properties: memory_type.properties,
+                heap_index: memory_type.heap_index,
+                heap_flags: heap.flags,
+                heap_size: heap.size,
+            }
+        })
+        .collect()
+}
+
+/// Layout the importer of a DmaBuf image created with explicit DRM modifiers has to use:
+/// the modifier the driver chose for `exportable_image` and the footprint of its planes.
+pub fn drm_import_properties<D: TestDevice>(
+    device: &D,
+    format: hal::format::Format,
+    exportable_image: &D::Image,
+) -> Result<DrmFormatImageProperties, String> {
+    let drm_modifier = unsafe { device.drm_format_modifier(exportable_image) }
+        .ok_or_else(|| String::from("The driver did not report the DRM format modifier of the image"))?;
+    let format_properties = device.format_properties(Some(format));
+    let selected_format_properties = format_properties.drm_format_properties.iter().find(|drm_format_properties|{
+        drm_format_properties.drm_modifier == drm_modifier
+    }).ok_or_else(|| format!("No DRM format properties for the modifier {:?} of the image", drm_modifier))?;
+
+    let plane_count = selected_format_properties.plane_count as usize;
+    if plane_count > MEMORY_PLANE_ASPECTS.len() {
+        return Err(format!("The modifier {:?} has {} memory planes", drm_modifier, plane_count));
+    }
+    Ok(DrmFormatImageProperties {
+        drm_modifier,
+        plane_layouts: plane_footprints(device, exportable_image, &MEMORY_PLANE_ASPECTS[..plane_count])
+    })
+}
+
+/// Aspects of the memory planes of an image created with a DRM format modifier.
+pub const MEMORY_PLANE_ASPECTS: [Aspects; 4] = [
+    Aspects::MEMORY_PLANE_0,
+    Aspects::MEMORY_PLANE_1,
+    Aspects::MEMORY_PLANE_2,
+    Aspects::MEMORY_PLANE_3,
+];
+
+/// Footprint of the first layer and level of each plane of `image`.
+pub fn plane_footprints<D: TestDevice>(
+    device: &D,
+    image: &D::Image,
+    aspects: &[Aspects],
+) -> Vec<SubresourceFootprint> {
+    aspects.iter().map(|&aspects|{
+        let subresource = Subresource {
+            aspects,
+            level: 0,
+            layer: 0
+        };
+        unsafe{device.get_image_subresource_footprint(image,subresource)}
+    }).collect()
+}
+
+/// Compares the plane footprints of the exported and the imported image.
+fn footprint_check<D: TestDevice>(
+    device: &D,
+    exportable: &ExternalResource<D>,
+    imported: &ExternalResource<D>,
+    aspects: &[Aspects],
+    properties: Option<ExternalMemoryProperties>,
+) -> TestResult {
+    let (exportable_image, imported_image) = match (exportable.image(), imported.image()) {
+        (Ok(exportable_image), Ok(imported_image)) => (exportable_image, imported_image),
+        (Err(err), _) | (_, Err(err)) => return TestResult::failed("get_image_subresource_footprint", err, properties),
+    };
+    let exported_footprints = plane_footprints(device, exportable_image, aspects);
+    let imported_footprints = plane_footprints(device, imported_image, aspects);
+    for (plane, (exported, imported)) in exported_footprints.iter().zip(&imported_footprints).enumerate() {
+        if exported != imported {
+            return TestResult::failed(
+                "get_image_subresource_footprint",
+                format!("Plane {} ({:?}): exporter {:?}, importer {:?}", plane, aspects[plane], exported, imported),
+                properties,
+            );
+        }
+    }
+    TestResult::Success
+}
+
+/// Imports `exported_memory` as the resource described by `parameters`.
+/// `size` is only used by buffers.
+pub fn import_resource<'a, D: TestDevice>(
+    device: &'a D,
+    parameters: &Parameters,
+    exported_memory: PlatformMemory,
+    drm_properties: Option<DrmFormatImageProperties>,
+    memory_types: u32,
+    size: u64,
+) -> Result<ExternalResource<'a, D>, ExternalResourceError> {
+    match parameters.clone() {
+        Parameters::Buffer{external_memory_type,buffer_usage,buffer_flags}=>{
+            let external_memory = external_buffer_memory(external_memory_type, exported_memory);
+            let (buffer, memory) = unsafe {
+                device.import_external_buffer(
+                    external_memory,
+                    buffer_usage,
+                    buffer_flags,
+                    memory_types,
+                    size,
+                )
+            }?;
+            Ok(ExternalResource::new(device, Resource::Buffer(buffer), memory))
+        }
+        Parameters::Image{external_memory_type,kind,mip_levels,format,tiling,usage,sparse,view_caps}=>{
+            let external_memory = external_image_memory(external_memory_type, exported_memory, drm_properties);
+            let (image, memory) = unsafe {
+                device.import_external_image(
+                    external_memory,
+                    kind,mip_levels,format,tiling,usage,sparse,view_caps,
+                    memory_types
+                )
+            }?;
+            Ok(ExternalResource::new(device, Resource::Image(image), memory))
+        }
+    }
+}
+
+fn external_buffer_memory(external_memory_type: ExternalBufferMemoryType, exported_memory: PlatformMemory) -> ExternalBufferMemory {
+    match external_memory_type {
+        #[cfg(unix)]
+        ExternalMemoryType::OpaqueFd => ExternalBufferMemory::OpaqueFd(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalMemoryType::OpaqueWin32 => ExternalBufferMemory::OpaqueWin32(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalMemoryType::OpaqueWin32Kmt => ExternalBufferMemory::OpaqueWin32Kmt(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalMemoryType::D3D11Texture => ExternalBufferMemory::D3D11Texture(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalMemoryType::D3D11TextureKmt => ExternalBufferMemory::D3D11TextureKmt(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalMemoryType::D3D12Heap => ExternalBufferMemory::D3D12Heap(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalMemoryType::D3D12Resource => ExternalBufferMemory::D3D12Resource(exported_memory.try_into().unwrap()),
+        #[cfg(any(target_os = "linux", target_os = "android", doc))]
+        ExternalMemoryType::DmaBuf => ExternalBufferMemory::DmaBuf(exported_memory.try_into().unwrap()),
+        #[cfg(any(target_os = "android", doc))]
+        ExternalMemoryType::AndroidHardwareBuffer => ExternalBufferMemory::AndroidHardwareBuffer(exported_memory.try_into().unwrap()),
+        ExternalMemoryType::HostAllocation => ExternalBufferMemory::HostAllocation(exported_memory.try_into().unwrap()),
+        ExternalMemoryType::HostMappedForeignMemory => ExternalBufferMemory::HostMappedForeignMemory(exported_memory.try_into().unwrap()),
+    }
+}
+
+fn external_image_memory(
+    external_memory_type: ExternalImageMemoryType,
+    exported_memory: PlatformMemory,
+    drm_properties: Option<DrmFormatImageProperties>,
+) -> ExternalImageMemory {
+    match external_memory_type {
+        #[cfg(unix)]
+        ExternalImageMemoryType::OpaqueFd => ExternalImageMemory::OpaqueFd(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalImageMemoryType::OpaqueWin32 => ExternalImageMemory::OpaqueWin32(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalImageMemoryType::OpaqueWin32Kmt => ExternalImageMemory::OpaqueWin32Kmt(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalImageMemoryType::D3D11Texture => ExternalImageMemory::D3D11Texture(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalImageMemoryType::D3D11TextureKmt => ExternalImageMemory::D3D11TextureKmt(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalImageMemoryType::D3D12Heap => ExternalImageMemory::D3D12Heap(exported_memory.try_into().unwrap()),
+        #[cfg(windows)]
+        ExternalImageMemoryType::D3D12Resource => ExternalImageMemory::D3D12Resource(exported_memory.try_into().unwrap()),
+        #[cfg(any(target_os = "linux", target_os = "android", doc))]
+        ExternalImageMemoryType::DmaBuf(_drm_modifiers)=> ExternalImageMemory::DmaBuf(exported_memory.try_into().unwrap(),drm_properties),
+        #[cfg(any(target_os = "android", doc))]
+        ExternalImageMemoryType::AndroidHardwareBuffer => ExternalImageMemory::AndroidHardwareBuffer(exported_memory.try_into().unwrap()),
+        ExternalImageMemoryType::HostAllocation => ExternalImageMemory::HostAllocation(exported_memory.try_into().unwrap()),
+        ExternalImageMemoryType::HostMappedForeignMemory => ExternalImageMemory::HostMappedForeignMemory(exported_memory.try_into().unwrap()),
+    }
+}
+
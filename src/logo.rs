@@ -0,0 +1,78 @@
+//! Round trip of `logo.png` through exported linear images.
+
+use gfx_hal as hal;
+use hal::external_memory::ExternalImageMemoryType;
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+use crate::image_data::ImageContent;
+use crate::{Parameters, TestCase};
+
+const LOGO: &[u8] = include_bytes!("../logo.png");
+
+pub fn logo() -> RgbaImage {
+    image::load_from_memory_with_format(LOGO, image::ImageFormat::Png)
+        .expect("logo.png is not a valid PNG")
+        .to_rgba8()
+}
+
+fn logo_parameters(external_memory_type: ExternalImageMemoryType) -> Parameters {
+    let logo = logo();
+    Parameters::Image {
+        external_memory_type,
+        kind: hal::image::Kind::D2(logo.width(), logo.height(), 1, 1),
+        mip_levels: 1,
+        format: hal::format::Format::Rgba8Srgb,
+        tiling: hal::image::Tiling::Linear,
+        usage: hal::image::Usage::TRANSFER_DST | hal::image::Usage::SAMPLED,
+        sparse: hal::memory::SparseFlags::empty(),
+        view_caps: hal::image::ViewCapabilities::empty(),
+    }
+}
+
+pub fn logo_test_cases() -> Vec<TestCase> {
+    let mut external_memory_types = Vec::new();
+    #[cfg(unix)]
+    external_memory_types.push(("OPAQUE_FD", ExternalImageMemoryType::OpaqueFd));
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    external_memory_types.push(("DMA_BUF", ExternalImageMemoryType::DmaBuf(Vec::new())));
+    external_memory_types.push(("HOST_ALLOCATION", ExternalImageMemoryType::HostAllocation));
+
+    external_memory_types
+        .into_iter()
+        .map(|(name, external_memory_type)| TestCase {
+            image_content: ImageContent::Logo,
+            ..TestCase::new(format!("LOGO {}", name), logo_parameters(external_memory_type))
+        })
+        .collect()
+}
+
+/// Writes `expected`, `actual` and their difference to `dir`. Each color channel
+/// of the difference is the absolute difference of the channel or of the alpha,
+/// whichever is larger, and the difference is opaque, so the matching pixels are
+/// black.
+pub fn write_mismatch_images(
+    dir: &Path,
+    name: &str,
+    expected: &RgbaImage,
+    actual: &RgbaImage,
+) -> image::ImageResult<()> {
+    std::fs::create_dir_all(dir)?;
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let diff = RgbaImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let (expected, actual) = (expected.get_pixel(x, y), actual.get_pixel(x, y));
+        let channel_diff = |channel: usize| {
+            let diff = |channel: usize| {
+                expected[channel].max(actual[channel]) - expected[channel].min(actual[channel])
+            };
+            diff(channel).max(diff(3))
+        };
+        Rgba([channel_diff(0), channel_diff(1), channel_diff(2), 255])
+    });
+    expected.save(dir.join(format!("{}.expected.png", name)))?;
+    actual.save(dir.join(format!("{}.actual.png", name)))?;
+    diff.save(dir.join(format!("{}.diff.png", name)))
+}
@@ -0,0 +1,216 @@
+//! Test cases generated from the capabilities of the adapter.
+//!
+//! Every handle type is crossed with every buffer usage, or with a range of image
+//! formats, tilings, kinds and usages. The external memory properties of each
+//! combination are queried and the ones the adapter claims to be exportable or
+//! importable become test cases. Optimally tiled images get the transfer usages
+//! their data is checked with, or no data check if these are not supported.
+
+use gfx_hal as hal;
+use hal::external_memory::{ExternalImageMemoryType, ExternalMemoryProperties, ExternalMemoryType};
+use hal::format::Format;
+use hal::image::{Kind, Tiling};
+
+use crate::{Parameters, TestCase, TestDevice, Verification};
+
+/// Handle types available on this platform.
+pub fn external_memory_types() -> Vec<ExternalMemoryType> {
+    let mut types = Vec::new();
+    #[cfg(unix)]
+    types.push(ExternalMemoryType::OpaqueFd);
+    #[cfg(windows)]
+    types.extend_from_slice(&[
+        ExternalMemoryType::OpaqueWin32,
+        ExternalMemoryType::OpaqueWin32Kmt,
+        ExternalMemoryType::D3D11Texture,
+        ExternalMemoryType::D3D11TextureKmt,
+        ExternalMemoryType::D3D12Heap,
+        ExternalMemoryType::D3D12Resource,
+    ]);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    types.push(ExternalMemoryType::DmaBuf);
+    #[cfg(target_os = "android")]
+    types.push(ExternalMemoryType::AndroidHardwareBuffer);
+    types.push(ExternalMemoryType::HostAllocation);
+    types.push(ExternalMemoryType::HostMappedForeignMemory);
+    types
+}
+
+/// The image handle type of `external_memory_type`. DmaBuf images let the driver
+/// choose the layout.
+pub fn external_image_memory_type(external_memory_type: ExternalMemoryType) -> ExternalImageMemoryType {
+    match external_memory_type {
+        #[cfg(unix)]
+        ExternalMemoryType::OpaqueFd => ExternalImageMemoryType::OpaqueFd,
+        #[cfg(windows)]
+        ExternalMemoryType::OpaqueWin32 => ExternalImageMemoryType::OpaqueWin32,
+        #[cfg(windows)]
+        ExternalMemoryType::OpaqueWin32Kmt => ExternalImageMemoryType::OpaqueWin32Kmt,
+        #[cfg(windows)]
+        ExternalMemoryType::D3D11Texture => ExternalImageMemoryType::D3D11Texture,
+        #[cfg(windows)]
+        ExternalMemoryType::D3D11TextureKmt => ExternalImageMemoryType::D3D11TextureKmt,
+        #[cfg(windows)]
+        ExternalMemoryType::D3D12Heap => ExternalImageMemoryType::D3D12Heap,
+        #[cfg(windows)]
+        ExternalMemoryType::D3D12Resource => ExternalImageMemoryType::D3D12Resource,
+        #[cfg(any(target_os = "linux", target_os = "android", doc))]
+        ExternalMemoryType::DmaBuf => ExternalImageMemoryType::DmaBuf(Vec::new()),
+        #[cfg(any(target_os = "android", doc))]
+        ExternalMemoryType::AndroidHardwareBuffer => ExternalImageMemoryType::AndroidHardwareBuffer,
+        ExternalMemoryType::HostAllocation => ExternalImageMemoryType::HostAllocation,
+        ExternalMemoryType::HostMappedForeignMemory => ExternalImageMemoryType::HostMappedForeignMemory,
+    }
+}
+
+pub const FORMATS: [Format; 10] = [
+    Format::R8Unorm,
+    Format::Rg8Unorm,
+    Format::Rgba8Unorm,
+    Format::Rgba8Srgb,
+    Format::Bgra8Unorm,
+    Format::Bgra8Srgb,
+    Format::A2b10g10r10UnormPack32,
+    Format::R16Unorm,
+    Format::Rgba16Sfloat,
+    Format::Rgba32Sfloat,
+];
+
+pub const TILINGS: [Tiling; 2] = [Tiling::Linear, Tiling::Optimal];
+
+pub const KINDS: [Kind; 4] = [
+    Kind::D1(256, 1),
+    Kind::D2(256, 256, 1, 1),
+    Kind::D2(256, 256, 4, 1),
+    Kind::D3(64, 64, 4),
+];
+
+/// Number of dimensions of `kind`, as expected by `external_image_properties`.
+pub fn kind_dimensions(kind: Kind) -> u8 {
+    match kind {
+        Kind::D1(..) => 1,
+        Kind::D2(..) => 2,
+        Kind::D3(..) => 3,
+    }
+}
+
+/// The single bit flags of `all`.
+fn single_bits<T>(all: u32, from_bits: impl Fn(u32) -> Option<T>) -> Vec<T> {
+    (0..32)
+        .map(|bit| 1u32 << bit)
+        .filter(|bit| all & bit != 0)
+        .filter_map(from_bits)
+        .collect()
+}
+
+pub fn buffer_usages() -> Vec<hal::buffer::Usage> {
+    single_bits(hal::buffer::Usage::all().bits(), hal::buffer::Usage::from_bits)
+}
+
+pub fn image_usages() -> Vec<hal::image::Usage> {
+    single_bits(hal::image::Usage::all().bits(), hal::image::Usage::from_bits)
+}
+
+fn is_claimed(properties: ExternalMemoryProperties) -> bool {
+    properties.intersects(ExternalMemoryProperties::EXPORTABLE | ExternalMemoryProperties::IMPORTABLE)
+}
+
+pub fn matrix_test_cases<D: TestDevice>(device: &D) -> Vec<TestCase> {
+    let mut test_cases = matrix_buffer_test_cases(device);
+    test_cases.extend(matrix_image_test_cases(device));
+    test_cases
+}
+
+pub fn matrix_buffer_test_cases<D: TestDevice>(device: &D) -> Vec<TestCase> {
+    let usages = buffer_usages();
+    let mut test_cases = Vec::new();
+    for external_memory_type in external_memory_types() {
+        for &usage in &usages {
+            let sparse = hal::memory::SparseFlags::empty();
+            let properties = device.external_buffer_properties(usage, sparse, external_memory_type);
+            if !is_claimed(properties) {
+                continue;
+            }
+            test_cases.push(TestCase::new(
+                format!("{:?} {:?}", external_memory_type, usage),
+                Parameters::Buffer {
+                    external_memory_type,
+                    buffer_usage: usage,
+                    buffer_flags: sparse,
+                },
+            ));
+        }
+    }
+    test_cases
+}
+
+pub fn matrix_image_test_cases<D: TestDevice>(device: &D) -> Vec<TestCase> {
+    let usages = image_usages();
+    let mut test_cases = Vec::new();
+    for external_memory_type in external_memory_types() {
+        for &format in FORMATS.iter() {
+            for &tiling in TILINGS.iter() {
+                for &kind in KINDS.iter() {
+                    let view_caps = hal::image::ViewCapabilities::empty();
+                    let is_claimed_usage = |usage| match device.external_image_properties(
+                        format,
+                        kind_dimensions(kind),
+                        tiling,
+                        usage,
+                        view_caps,
+                        external_memory_type,
+                    ) {
+                        Ok(properties) => is_claimed(properties),
+                        Err(err) => {
+                            log::debug!(
+                                "{:?} {:?} {:?} {:?} {:?}: {}",
+                                external_memory_type, format, tiling, kind, usage, err
+                            );
+                            false
+                        }
+                    };
+                    let mut tested_usages = Vec::new();
+                    for &usage in &usages {
+                        // Optimally tiled images have no footprints to be mapped through,
+                        // their data is copied if the usage allows it
+                        let transfer_usage =
+                            usage | hal::image::Usage::TRANSFER_SRC | hal::image::Usage::TRANSFER_DST;
+                        let (usage, verification) = match tiling {
+                            Tiling::Optimal if is_claimed_usage(transfer_usage) => {
+                                (transfer_usage, Verification::Transfer)
+                            }
+                            Tiling::Optimal if is_claimed_usage(usage) => (usage, Verification::Unchecked),
+                            Tiling::Optimal => continue,
+                            _ if is_claimed_usage(usage) => (usage, Verification::Map),
+                            _ => continue,
+                        };
+                        if tested_usages.contains(&usage) {
+                            continue;
+                        }
+                        tested_usages.push(usage);
+                        test_cases.push(TestCase {
+                            verification,
+                            ..TestCase::new(
+                                format!(
+                                    "{:?} {:?} {:?} {:?} {:?}",
+                                    external_memory_type, format, tiling, kind, usage
+                                ),
+                                Parameters::Image {
+                                    external_memory_type: external_image_memory_type(external_memory_type),
+                                    kind,
+                                    mip_levels: 1,
+                                    format,
+                                    tiling,
+                                    usage,
+                                    sparse: hal::memory::SparseFlags::empty(),
+                                    view_caps,
+                                },
+                            )
+                        });
+                    }
+                }
+            }
+        }
+    }
+    test_cases
+}
@@ -0,0 +1,1315 @@
+//! A GPU-less implementation of `TestDevice`.
+//!
+//! Fd based memory (`OpaqueFd` and `DmaBuf`) is backed by a memfd that stays mapped
+//! for the whole lifetime of the memory object, while host memory types are backed
+//! by plain aligned host allocations. Fd based memory of the non-coherent memory
+//! type is mapped through a host shadow copy, which only `flush_memory` and
+//! `invalidate_memory` synchronize with the storage, so that a missing flush or
+//! invalidation corrupts the data like on a real device. This is enough to exercise the whole
+//! export -> import -> data_check flow on machines without a GPU. Transfers are
+//! copies between the storages of the resources, and the compute shaders are
+//! run on the CPU.
+//!
+//! Every format has the DRM format modifiers of `drm_modifiers`. An image keeps
+//! the modifier it was created with, and importing its memory with another one
+//! fails like it does on a driver checking the layout.
+
+use gfx_hal as hal;
+use hal::external_memory::*;
+use std::cell::{Cell, RefCell};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::{HalError, LiveObjects, TestDevice};
+
+const HEAP_SIZE: u64 = 256 * 1024 * 1024;
+const HOST_POINTER_ALIGNMENT: u64 = 4096;
+const ROW_PITCH_ALIGNMENT: u64 = 256;
+const NON_COHERENT_ATOM_SIZE: u64 = 64;
+/// A coherent, a non-coherent cached and a device local memory type, all backed
+/// by the same storage.
+const MEMORY_TYPES_MASK: u32 = 0b111;
+/// Size of a metadata memory plane, after the texels.
+const METADATA_PLANE_SIZE: u64 = 4096;
+
+/// The memory type that is not coherent, mapped through a shadow copy.
+const NON_COHERENT_MEMORY_TYPE: u32 = 1;
+/// The memory type that can not be mapped, only accessed through transfers.
+const DEVICE_LOCAL_MEMORY_TYPE: u32 = 2;
+
+pub struct MockBuffer {
+    size: u64,
+    /// Start of the storage of the memory of the buffer.
+    ptr: *mut u8,
+}
+
+pub struct MockImage {
+    /// Footprint of the first layer of each level of each plane.
+    footprints: Vec<Vec<hal::image::SubresourceFootprint>>,
+    /// Texel size of each plane.
+    texel_sizes: Vec<u32>,
+    drm_modifier: Option<hal::format::DrmModifier>,
+    /// Footprint of the metadata memory planes of the DRM format modifier, after
+    /// the format planes.
+    metadata_planes: Vec<hal::image::SubresourceFootprint>,
+    /// Start of the storage of the memory of each plane, the same one unless the
+    /// image is disjoint.
+    ptrs: Vec<*mut u8>,
+}
+
+/// A DRM format modifier of every format.
+#[derive(Debug, Clone, Copy)]
+struct MockDrmModifier {
+    drm_modifier: u64,
+    /// Memory planes holding metadata, after the format planes.
+    metadata_planes: u32,
+    valid_usages: hal::format::ImageFeature,
+}
+
+/// `DRM_FORMAT_MOD_LINEAR`, `I915_FORMAT_MOD_X_TILED` and
+/// `I915_FORMAT_MOD_Y_TILED_CCS`. The test cases use none of the usages of the
+/// second one, and can not check the data of the third one.
+fn drm_modifiers() -> [MockDrmModifier; 3] {
+    use hal::format::ImageFeature;
+    let transfer_sampled = ImageFeature::TRANSFER_SRC | ImageFeature::TRANSFER_DST | ImageFeature::SAMPLED;
+    [
+        MockDrmModifier {
+            drm_modifier: 0,
+            metadata_planes: 0,
+            valid_usages: transfer_sampled | ImageFeature::COLOR_ATTACHMENT,
+        },
+        MockDrmModifier {
+            drm_modifier: (1 << 56) | 1,
+            metadata_planes: 0,
+            valid_usages: ImageFeature::STORAGE,
+        },
+        MockDrmModifier {
+            drm_modifier: (1 << 56) | 4,
+            metadata_planes: 1,
+            valid_usages: transfer_sampled,
+        },
+    ]
+}
+
+/// The modifier of `drm_modifiers` that `drm_modifier` is, if any.
+fn find_drm_modifier(drm_modifier: hal::format::DrmModifier) -> Option<MockDrmModifier> {
+    drm_modifiers()
+        .iter()
+        .copied()
+        .find(|modifier| hal::format::DrmModifier::from(modifier.drm_modifier) == drm_modifier)
+}
+
+pub struct MockMemory {
+    storage: Storage,
+    size: u64,
+    cpu_visible: bool,
+    /// The host cache of non-coherent fd based memory, where it is mapped. Host
+    /// allocations are host memory already, so they are mapped directly.
+    shadow: Option<Storage>,
+}
+
+impl MockMemory {
+    /// Memory of the first memory type of `type_mask`, backed by the storage
+    /// `new_storage` creates, last so that a failed import does not take the
+    /// ownership of its handle.
+    fn new(
+        size: u64,
+        type_mask: u32,
+        fd_based: bool,
+        new_storage: impl FnOnce() -> Result<Storage, ExternalResourceError>,
+    ) -> Result<Self, ExternalResourceError> {
+        let memory_type = memory_type(type_mask)?;
+        let shadow = match fd_based && memory_type == NON_COHERENT_MEMORY_TYPE {
+            // Left zeroed, like a cache holding stale data
+            true => Some(Storage::new_host(size)?),
+            false => None,
+        };
+        let storage = match new_storage() {
+            Ok(storage) => storage,
+            Err(err) => {
+                if let Some(shadow) = shadow {
+                    unsafe { shadow.release(size) };
+                }
+                return Err(err);
+            }
+        };
+        Ok(Self {
+            storage,
+            size,
+            cpu_visible: memory_type != DEVICE_LOCAL_MEMORY_TYPE,
+            shadow,
+        })
+    }
+
+    /// Where the host accesses the memory.
+    fn mapping(&self) -> *mut u8 {
+        self.shadow.as_ref().unwrap_or(&self.storage).ptr()
+    }
+
+    /// Copies `segment` from the shadow to the storage if `flush`, the other way
+    /// otherwise. Nothing to do without a shadow.
+    unsafe fn synchronize(&self, segment: hal::memory::Segment, flush: bool) {
+        check_atom_alignment(self, segment);
+        let shadow = match &self.shadow {
+            Some(shadow) => shadow.ptr(),
+            None => return,
+        };
+        let end = segment.size.map_or(self.size, |size| segment.offset + size).min(self.size);
+        if segment.offset >= end {
+            return;
+        }
+        let (offset, len) = (segment.offset as usize, (end - segment.offset) as usize);
+        let storage = self.storage.ptr();
+        if flush {
+            std::ptr::copy_nonoverlapping(shadow.add(offset), storage.add(offset), len);
+        } else {
+            std::ptr::copy_nonoverlapping(storage.add(offset), shadow.add(offset), len);
+        }
+    }
+}
+
+enum Storage {
+    Fd { fd: RawFd, ptr: *mut u8 },
+    Host { ptr: *mut u8, layout: std::alloc::Layout },
+    Foreign(*mut u8),
+}
+
+impl Storage {
+    fn ptr(&self) -> *mut u8 {
+        match self {
+            Self::Fd { ptr, .. } => *ptr,
+            Self::Host { ptr, .. } => *ptr,
+            Self::Foreign(ptr) => *ptr,
+        }
+    }
+
+    fn new_fd(size: u64) -> Result<Self, ExternalResourceError> {
+        let fd = unsafe {
+            libc::memfd_create(
+                b"gfx_external_memory_test\0".as_ptr() as *const libc::c_char,
+                libc::MFD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(hal::device::OutOfMemory::Host.into());
+        }
+        if unsafe { libc::ftruncate(fd, size as libc::off_t) } < 0 {
+            unsafe { libc::close(fd) };
+            return Err(hal::device::OutOfMemory::Host.into());
+        }
+        Self::map_fd(fd, size).map_err(|err| {
+            unsafe { libc::close(fd) };
+            err
+        })
+    }
+
+    fn import_fd(fd: RawFd, size: u64) -> Result<Self, ExternalResourceError> {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } < 0 || (stat.st_size as u64) < size {
+            return Err(ExternalResourceError::InvalidExternalHandle);
+        }
+        Self::map_fd(fd, size)
+    }
+
+    /// Maps `fd`, taking ownership of it on success only, like a failed
+    /// Vulkan import does.
+    fn map_fd(fd: RawFd, size: u64) -> Result<Self, ExternalResourceError> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(hal::device::OutOfMemory::Host.into());
+        }
+        Ok(Self::Fd {
+            fd,
+            ptr: ptr as *mut u8,
+        })
+    }
+
+    fn new_host(size: u64) -> Result<Self, ExternalResourceError> {
+        let layout =
+            std::alloc::Layout::from_size_align(size as usize, HOST_POINTER_ALIGNMENT as usize)
+                .map_err(|_| ExternalResourceError::from(hal::device::OutOfMemory::Host))?;
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(hal::device::OutOfMemory::Host.into());
+        }
+        Ok(Self::Host { ptr, layout })
+    }
+
+    fn import_ptr(ptr: Ptr) -> Result<Self, ExternalResourceError> {
+        let ptr = ptr.as_raw_ptr() as *mut u8;
+        if ptr.is_null() || ptr as u64 % HOST_POINTER_ALIGNMENT != 0 {
+            return Err(ExternalResourceError::InvalidExternalHandle);
+        }
+        Ok(Self::Foreign(ptr))
+    }
+
+    unsafe fn release(self, size: u64) {
+        match self {
+            Self::Fd { fd, ptr } => {
+                libc::munmap(ptr as *mut libc::c_void, size as usize);
+                libc::close(fd);
+            }
+            Self::Host { ptr, layout } => std::alloc::dealloc(ptr, layout),
+            Self::Foreign(_) => {}
+        }
+    }
+}
+
+pub struct MockDevice {
+    live: Cell<LiveObjects>,
+    /// The modifier of the images created with one, by inode of their memfd.
+    drm_modifiers: RefCell<Vec<(u64, hal::format::DrmModifier)>>,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        Self {
+            live: Cell::new(LiveObjects::default()),
+            drm_modifiers: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn live_objects(&self) -> LiveObjects {
+        self.live.get()
+    }
+
+    fn track(&self, f: impl FnOnce(&mut LiveObjects)) {
+        let mut live = self.live.get();
+        f(&mut live);
+        self.live.set(live);
+    }
+
+    fn supported(&self, external_memory_type: ExternalMemoryType) -> ExternalMemoryProperties {
+        match external_memory_type {
+            ExternalMemoryType::OpaqueFd
+            | ExternalMemoryType::DmaBuf
+            | ExternalMemoryType::HostAllocation
+            | ExternalMemoryType::HostMappedForeignMemory => {
+                ExternalMemoryProperties::EXPORTABLE
+                    | ExternalMemoryProperties::IMPORTABLE
+                    | ExternalMemoryProperties::EXPORTABLE_FROM_IMPORTED
+            }
+            #[allow(unreachable_patterns)]
+            _ => ExternalMemoryProperties::empty(),
+        }
+    }
+
+    fn allocate(
+        &self,
+        external_memory_type: ExternalMemoryType,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<MockMemory, ExternalResourceError> {
+        let fd_based = matches!(external_memory_type, ExternalMemoryType::OpaqueFd | ExternalMemoryType::DmaBuf);
+        let memory = MockMemory::new(size, type_mask, fd_based, || match external_memory_type {
+            ExternalMemoryType::OpaqueFd | ExternalMemoryType::DmaBuf => Storage::new_fd(size),
+            ExternalMemoryType::HostAllocation | ExternalMemoryType::HostMappedForeignMemory => {
+                Storage::new_host(size)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(ExternalResourceError::InvalidExternalHandle),
+        })?;
+        self.track(|live| live.memories += 1);
+        Ok(memory)
+    }
+
+    fn import(
+        &self,
+        external_memory: ExternalMemory,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<MockMemory, ExternalResourceError> {
+        let fd_based = matches!(external_memory, ExternalMemory::OpaqueFd(_) | ExternalMemory::DmaBuf(_));
+        let memory = MockMemory::new(size, type_mask, fd_based, || match external_memory {
+            ExternalMemory::OpaqueFd(fd) | ExternalMemory::DmaBuf(fd) => Storage::import_fd(fd.as_raw_fd(), size),
+            ExternalMemory::HostAllocation(ptr) | ExternalMemory::HostMappedForeignMemory(ptr) => {
+                Storage::import_ptr(ptr)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(ExternalResourceError::InvalidExternalHandle),
+        })?;
+        self.track(|live| live.memories += 1);
+        Ok(memory)
+    }
+
+    /// An image bound to `memories`, a single one or one per plane if it is
+    /// disjoint.
+    fn image(
+        &self,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        drm_modifier: Option<hal::format::DrmModifier>,
+        memories: &[MockMemory],
+    ) -> MockImage {
+        self.track(|live| live.images += 1);
+        let planes = crate::image_data::plane_descs(format);
+        MockImage {
+            footprints: self.image_footprints(kind, mip_levels, format, memories.len() > 1),
+            texel_sizes: planes.iter().map(|plane| plane.texel_size).collect(),
+            drm_modifier,
+            metadata_planes: Vec::new(),
+            ptrs: (0..planes.len())
+                .map(|plane| memories[plane.min(memories.len() - 1)].storage.ptr())
+                .collect(),
+        }
+    }
+
+    /// Copies `regions` between the packed rows of `buffer` and `image`, in the
+    /// direction of `to_image`.
+    unsafe fn copy_regions(
+        &self,
+        buffer: &MockBuffer,
+        image: &MockImage,
+        regions: &[hal::command::BufferImageCopy],
+        to_image: bool,
+    ) {
+        for region in regions {
+            let aspects = region.image_layers.aspects;
+            let plane = plane_index(aspects).min(image.texel_sizes.len() - 1);
+            let texel_size = image.texel_sizes[plane] as u64;
+            let extent = region.image_extent;
+            let buffer_width = if region.buffer_width == 0 { extent.width } else { region.buffer_width };
+            let buffer_height = if region.buffer_height == 0 { extent.height } else { region.buffer_height };
+            let buffer_row_pitch = buffer_width as u64 * texel_size;
+            let buffer_depth_pitch = buffer_row_pitch * buffer_height as u64;
+            let row_len = extent.width as u64 * texel_size;
+            for (index, layer) in region.image_layers.layers.clone().enumerate() {
+                let subresource = hal::image::Subresource { aspects, level: region.image_layers.level, layer };
+                let footprint = self.get_image_subresource_footprint(image, subresource);
+                for z in 0..extent.depth as u64 {
+                    for y in 0..extent.height as u64 {
+                        let buffer_offset = region.buffer_offset
+                            + (index as u64 * extent.depth as u64 + z) * buffer_depth_pitch
+                            + y * buffer_row_pitch;
+                        let image_offset = footprint.slice.start
+                            + (region.image_offset.z as u64 + z) * footprint.depth_pitch
+                            + (region.image_offset.y as u64 + y) * footprint.row_pitch
+                            + region.image_offset.x as u64 * texel_size;
+                        assert!(buffer_offset + row_len <= buffer.size, "Region {:?} out of the buffer", region);
+                        assert!(image_offset + row_len <= footprint.slice.end, "Region {:?} out of the image", region);
+                        let (buffer_row, image_row) =
+                            (buffer.ptr.add(buffer_offset as usize), image.ptrs[plane].add(image_offset as usize));
+                        if to_image {
+                            std::ptr::copy(buffer_row, image_row, row_len as usize);
+                        } else {
+                            std::ptr::copy(image_row, buffer_row, row_len as usize);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Footprints of the first layer of each level of each plane of an image, laid
+    /// out plane after plane, then level after level, or from the start of the
+    /// memory of each plane of a `disjoint` image. Only YCbCr formats have more
+    /// than one plane.
+    fn image_footprints(
+        &self,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        disjoint: bool,
+    ) -> Vec<Vec<hal::image::SubresourceFootprint>> {
+        let mut offset = 0;
+        crate::image_data::plane_descs(format)
+            .into_iter()
+            .map(|plane| {
+                if disjoint {
+                    offset = 0;
+                }
+                (0..mip_levels)
+                    .map(|level| {
+                        let extent = kind.level_extent(level);
+                        let width = (extent.width / plane.width_divisor).max(1) as u64;
+                        let height = (extent.height / plane.height_divisor).max(1) as u64;
+                        let row_pitch = ((width * plane.texel_size as u64 + ROW_PITCH_ALIGNMENT - 1)
+                            / ROW_PITCH_ALIGNMENT)
+                            * ROW_PITCH_ALIGNMENT;
+                        let depth_pitch = row_pitch * height;
+                        let array_pitch = depth_pitch * extent.depth as u64;
+                        let footprint = hal::image::SubresourceFootprint {
+                            slice: offset..offset + array_pitch,
+                            row_pitch,
+                            array_pitch,
+                            depth_pitch,
+                        };
+                        offset += array_pitch * kind.num_layers() as u64;
+                        footprint
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The size of the memory of each plane of a `disjoint` image, of the single
+    /// memory of the image otherwise.
+    fn memory_sizes(
+        &self,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        disjoint: bool,
+    ) -> Vec<u64> {
+        let plane_ends = self
+            .image_footprints(kind, mip_levels, format, disjoint)
+            .iter()
+            .map(|plane| {
+                let last = plane.last().unwrap();
+                last.slice.start + last.array_pitch * kind.num_layers() as u64
+            })
+            .collect::<Vec<_>>();
+        match disjoint {
+            true => plane_ends,
+            false => vec![*plane_ends.last().unwrap()],
+        }
+    }
+
+    fn image_size(
+        &self,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+    ) -> u64 {
+        self.memory_sizes(kind, mip_levels, format, false)[0]
+    }
+
+    /// Checks that an image of `format`, `image_size` bytes without metadata, can
+    /// be imported from `fd` with `drm_properties`: the modifier must be the one
+    /// the image was created with and the planes must fit in the memory, whose
+    /// size is returned.
+    fn check_drm_layout(
+        &self,
+        drm_properties: &hal::image::DrmFormatImageProperties,
+        format: hal::format::Format,
+        fd: RawFd,
+        image_size: u64,
+    ) -> Result<u64, ExternalResourceError> {
+        let modifier =
+            find_drm_modifier(drm_properties.drm_modifier).ok_or(ExternalResourceError::InvalidExternalHandle)?;
+        let created_with = inode(fd).and_then(|inode| {
+            self.drm_modifiers
+                .borrow()
+                .iter()
+                .find(|&&(other, _)| other == inode)
+                .map(|&(_, drm_modifier)| drm_modifier)
+        });
+        if created_with.map_or(false, |drm_modifier| drm_modifier != drm_properties.drm_modifier) {
+            return Err(ExternalResourceError::InvalidExternalHandle);
+        }
+        let plane_count = crate::image_data::plane_descs(format).len() + modifier.metadata_planes as usize;
+        let size = image_size + modifier.metadata_planes as u64 * METADATA_PLANE_SIZE;
+        if drm_properties.plane_layouts.len() != plane_count
+            || drm_properties.plane_layouts.iter().any(|layout| layout.slice.end > size)
+        {
+            return Err(ExternalResourceError::InvalidExternalHandle);
+        }
+        Ok(size)
+    }
+
+    /// Frees `memories`, allocated or imported for an image that could not be
+    /// created.
+    unsafe fn free_memories(&self, memories: Vec<MockMemory>) {
+        for memory in memories {
+            self.free_memory(memory);
+        }
+    }
+}
+
+impl TestDevice for MockDevice {
+    type Buffer = MockBuffer;
+    type Image = MockImage;
+    type Memory = MockMemory;
+
+    fn external_buffer_properties(
+        &self,
+        _usage: hal::buffer::Usage,
+        _sparse: hal::memory::SparseFlags,
+        external_memory_type: ExternalBufferMemoryType,
+    ) -> ExternalMemoryProperties {
+        self.supported(external_memory_type)
+    }
+
+    fn external_image_properties(
+        &self,
+        _format: hal::format::Format,
+        _dimensions: u8,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _view_caps: hal::image::ViewCapabilities,
+        external_memory_type: ExternalMemoryType,
+    ) -> Result<ExternalMemoryProperties, ExternalImagePropertiesError> {
+        Ok(self.supported(external_memory_type))
+    }
+
+    fn adapter_info(&self) -> hal::adapter::AdapterInfo {
+        hal::adapter::AdapterInfo {
+            name: "Mock device".into(),
+            vendor: 0,
+            device: 0,
+            device_type: hal::adapter::DeviceType::Cpu,
+        }
+    }
+
+    fn memory_properties(&self) -> hal::adapter::MemoryProperties {
+        hal::adapter::MemoryProperties {
+            memory_types: vec![
+                hal::adapter::MemoryType {
+                    properties: hal::memory::Properties::DEVICE_LOCAL
+                        | hal::memory::Properties::CPU_VISIBLE
+                        | hal::memory::Properties::COHERENT,
+                    heap_index: 0,
+                },
+                hal::adapter::MemoryType {
+                    properties: hal::memory::Properties::CPU_VISIBLE
+                        | hal::memory::Properties::CPU_CACHED,
+                    heap_index: 0,
+                },
+                hal::adapter::MemoryType {
+                    properties: hal::memory::Properties::DEVICE_LOCAL,
+                    heap_index: 0,
+                },
+            ],
+            memory_heaps: vec![hal::adapter::MemoryHeap {
+                size: HEAP_SIZE,
+                flags: hal::memory::HeapFlags::DEVICE_LOCAL,
+            }],
+        }
+    }
+
+    fn format_properties(&self, format: Option<hal::format::Format>) -> hal::format::Properties {
+        let plane_count = match format {
+            Some(format) => crate::image_data::plane_descs(format).len() as u32,
+            None => return hal::format::Properties::default(),
+        };
+        hal::format::Properties {
+            drm_format_properties: drm_modifiers()
+                .iter()
+                .map(|modifier| hal::format::DrmFormatProperties {
+                    drm_modifier: hal::format::DrmModifier::from(modifier.drm_modifier),
+                    plane_count: plane_count + modifier.metadata_planes,
+                    valid_usages: modifier.valid_usages,
+                })
+                .collect(),
+            ..hal::format::Properties::default()
+        }
+    }
+
+    fn min_imported_host_pointer_alignment(&self) -> u64 {
+        HOST_POINTER_ALIGNMENT
+    }
+
+    fn non_coherent_atom_size(&self) -> u64 {
+        NON_COHERENT_ATOM_SIZE
+    }
+
+    unsafe fn create_allocate_external_buffer(
+        &self,
+        external_memory_type: ExternalBufferMemoryType,
+        _usage: hal::buffer::Usage,
+        _sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError> {
+        let memory = self.allocate(external_memory_type, type_mask, size)?;
+        self.track(|live| live.buffers += 1);
+        Ok((MockBuffer { size, ptr: memory.storage.ptr() }, memory))
+    }
+
+    unsafe fn import_external_buffer(
+        &self,
+        external_memory: ExternalBufferMemory,
+        _usage: hal::buffer::Usage,
+        _sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError> {
+        let memory = self.import(external_memory, type_mask, size)?;
+        self.track(|live| live.buffers += 1);
+        Ok((MockBuffer { size, ptr: memory.storage.ptr() }, memory))
+    }
+
+    unsafe fn create_allocate_external_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _sparse: hal::memory::SparseFlags,
+        _view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError> {
+        // Like a driver, pick the first modifier
+        let drm_modifier = match &external_memory_type {
+            ExternalImageMemoryType::DmaBuf(drm_modifiers) => drm_modifiers.first().copied(),
+            _ => None,
+        };
+        let metadata_planes = match drm_modifier.map(find_drm_modifier) {
+            Some(None) => return Err(ExternalResourceError::InvalidExternalHandle),
+            Some(Some(modifier)) => modifier.metadata_planes,
+            None => 0,
+        };
+        let image_size = self.image_size(kind, mip_levels, format);
+        let memory = self.allocate(
+            external_memory_type.external_memory_type(),
+            type_mask,
+            image_size + metadata_planes as u64 * METADATA_PLANE_SIZE,
+        )?;
+        if let (Some(drm_modifier), Storage::Fd { fd, .. }) = (drm_modifier, &memory.storage) {
+            if let Some(inode) = inode(*fd) {
+                let mut drm_modifiers = self.drm_modifiers.borrow_mut();
+                drm_modifiers.retain(|&(other, _)| other != inode);
+                drm_modifiers.push((inode, drm_modifier));
+            }
+        }
+        let mut image = self.image(kind, mip_levels, format, drm_modifier, std::slice::from_ref(&memory));
+        image.metadata_planes = (0..metadata_planes as u64)
+            .map(|plane| {
+                let offset = image_size + plane * METADATA_PLANE_SIZE;
+                hal::image::SubresourceFootprint {
+                    slice: offset..offset + METADATA_PLANE_SIZE,
+                    row_pitch: ROW_PITCH_ALIGNMENT,
+                    array_pitch: METADATA_PLANE_SIZE,
+                    depth_pitch: METADATA_PLANE_SIZE,
+                }
+            })
+            .collect();
+        Ok((image, memory))
+    }
+
+    unsafe fn import_external_image(
+        &self,
+        external_memory: ExternalImageMemory,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _sparse: hal::memory::SparseFlags,
+        _view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError> {
+        let mut drm_properties = None;
+        let external_memory = match external_memory {
+            ExternalImageMemory::OpaqueFd(fd) => ExternalMemory::OpaqueFd(fd),
+            ExternalImageMemory::DmaBuf(fd, properties) => {
+                drm_properties = properties;
+                ExternalMemory::DmaBuf(fd)
+            }
+            ExternalImageMemory::HostAllocation(ptr) => ExternalMemory::HostAllocation(ptr),
+            ExternalImageMemory::HostMappedForeignMemory(ptr) => {
+                ExternalMemory::HostMappedForeignMemory(ptr)
+            }
+            #[allow(unreachable_patterns)]
+            _ => return Err(ExternalResourceError::InvalidExternalHandle),
+        };
+        let image_size = self.image_size(kind, mip_levels, format);
+        let size = match (&drm_properties, &external_memory) {
+            (Some(drm_properties), ExternalMemory::DmaBuf(fd)) => {
+                self.check_drm_layout(drm_properties, format, fd.as_raw_fd(), image_size)?
+            }
+            _ => image_size,
+        };
+        let memory = self.import(external_memory, type_mask, size)?;
+        let mut image = self.image(
+            kind,
+            mip_levels,
+            format,
+            drm_properties.as_ref().map(|drm_properties| drm_properties.drm_modifier),
+            std::slice::from_ref(&memory),
+        );
+        // The planes are where the importer says they are
+        if let Some(drm_properties) = drm_properties {
+            let mut plane_layouts = drm_properties.plane_layouts.into_iter();
+            for (plane, layout) in image.footprints.iter_mut().zip(&mut plane_layouts) {
+                plane[0] = layout;
+            }
+            image.metadata_planes = plane_layouts.collect();
+        }
+        Ok((image, memory))
+    }
+
+    fn disjoint_images(&self) -> bool {
+        true
+    }
+
+    unsafe fn create_allocate_external_disjoint_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _sparse: hal::memory::SparseFlags,
+        _view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError> {
+        let mut memories = Vec::new();
+        for size in self.memory_sizes(kind, mip_levels, format, true) {
+            match self.allocate(external_memory_type.external_memory_type(), type_mask, size) {
+                Ok(memory) => memories.push(memory),
+                Err(err) => {
+                    self.free_memories(memories);
+                    return Err(err);
+                }
+            }
+        }
+        Ok((self.image(kind, mip_levels, format, None, &memories), memories))
+    }
+
+    unsafe fn import_external_disjoint_image(
+        &self,
+        external_memories: Vec<ExternalImageMemory>,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _sparse: hal::memory::SparseFlags,
+        _view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError> {
+        let sizes = self.memory_sizes(kind, mip_levels, format, true);
+        if external_memories.len() != sizes.len() {
+            return Err(ExternalResourceError::InvalidExternalHandle);
+        }
+        // Every plane is imported from a duplicate of its handle, so that a failure
+        // leaves all the handles to the caller
+        let mut fds = Vec::new();
+        let mut memories = Vec::new();
+        for (external_memory, size) in external_memories.into_iter().zip(sizes) {
+            let fd = match external_memory {
+                ExternalImageMemory::OpaqueFd(fd) | ExternalImageMemory::DmaBuf(fd, None) => fd.as_raw_fd(),
+                _ => {
+                    self.free_memories(memories);
+                    return Err(ExternalResourceError::InvalidExternalHandle);
+                }
+            };
+            let duplicate = libc::dup(fd);
+            let memory = match duplicate {
+                -1 => Err(hal::device::OutOfMemory::Host.into()),
+                duplicate => self.import(ExternalMemory::OpaqueFd(duplicate.into()), type_mask, size).map_err(|err| {
+                    libc::close(duplicate);
+                    err
+                }),
+            };
+            match memory {
+                Ok(memory) => memories.push(memory),
+                Err(err) => {
+                    self.free_memories(memories);
+                    return Err(err);
+                }
+            }
+            fds.push(fd);
+        }
+        for fd in fds {
+            libc::close(fd);
+        }
+        Ok((self.image(kind, mip_levels, format, None, &memories), memories))
+    }
+
+    unsafe fn export_memory(
+        &self,
+        external_memory_type: ExternalMemoryType,
+        memory: &Self::Memory,
+    ) -> Result<PlatformMemory, ExternalMemoryExportError> {
+        match (external_memory_type, &memory.storage) {
+            (ExternalMemoryType::OpaqueFd, Storage::Fd { fd, .. })
+            | (ExternalMemoryType::DmaBuf, Storage::Fd { fd, .. }) => {
+                let fd = libc::dup(*fd);
+                if fd < 0 {
+                    return Err(ExternalMemoryExportError::TooManyObjects);
+                }
+                Ok(PlatformMemory::Fd(fd.into()))
+            }
+            _ => Err(ExternalMemoryExportError::UnsupportedParameters),
+        }
+    }
+
+    unsafe fn get_image_subresource_footprint(
+        &self,
+        image: &Self::Image,
+        subresource: hal::image::Subresource,
+    ) -> hal::image::SubresourceFootprint {
+        let index = plane_index(subresource.aspects);
+        let metadata_index = index.checked_sub(image.footprints.len());
+        if let Some(metadata) = metadata_index.and_then(|index| image.metadata_planes.get(index)) {
+            return metadata.clone();
+        }
+        let plane = &image.footprints[index.min(image.footprints.len() - 1)];
+        let mut footprint = plane[(subresource.level as usize).min(plane.len() - 1)].clone();
+        let offset = footprint.array_pitch * subresource.layer as u64;
+        footprint.slice = footprint.slice.start + offset..footprint.slice.end + offset;
+        footprint
+    }
+
+    unsafe fn drm_format_modifier(&self, image: &Self::Image) -> Option<hal::format::DrmModifier> {
+        image.drm_modifier
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: &mut Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<*mut u8, hal::device::MapError> {
+        if !memory.cpu_visible {
+            return Err(hal::device::MapError::MappingFailed);
+        }
+        if segment.offset >= memory.size {
+            return Err(hal::device::MapError::OutOfBounds);
+        }
+        Ok(memory.mapping().offset(segment.offset as isize))
+    }
+
+    unsafe fn unmap_memory(&self, _memory: &mut Self::Memory) {}
+
+    unsafe fn flush_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory> {
+        memory.synchronize(segment, true);
+        Ok(())
+    }
+
+    unsafe fn invalidate_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory> {
+        memory.synchronize(segment, false);
+        Ok(())
+    }
+
+    unsafe fn create_staging_buffer(&self, size: u64) -> Result<(Self::Buffer, Self::Memory), HalError> {
+        let storage = Storage::new_host(size.max(1)).map_err(|err| err.to_string())?;
+        let ptr = storage.ptr();
+        self.track(|live| {
+            live.buffers += 1;
+            live.memories += 1;
+        });
+        Ok((MockBuffer { size, ptr }, MockMemory { storage, size, cpu_visible: true, shadow: None }))
+    }
+
+    unsafe fn copy_buffer(
+        &self,
+        src: &Self::Buffer,
+        dst: &Self::Buffer,
+        size: u64,
+        _external: crate::ExternalBuffer,
+    ) -> Result<(), HalError> {
+        assert!(size <= src.size && size <= dst.size, "Copy of {} bytes out of the buffers", size);
+        std::ptr::copy(src.ptr, dst.ptr, size as usize);
+        Ok(())
+    }
+
+    unsafe fn copy_buffer_to_image(
+        &self,
+        buffer: &Self::Buffer,
+        image: &Self::Image,
+        regions: &[hal::command::BufferImageCopy],
+    ) -> Result<(), HalError> {
+        self.copy_regions(buffer, image, regions, true);
+        Ok(())
+    }
+
+    unsafe fn copy_image_to_buffer(
+        &self,
+        image: &Self::Image,
+        buffer: &Self::Buffer,
+        regions: &[hal::command::BufferImageCopy],
+    ) -> Result<(), HalError> {
+        self.copy_regions(buffer, image, regions, false);
+        Ok(())
+    }
+
+    unsafe fn dispatch(
+        &self,
+        shader: crate::compute::Shader,
+        buffers: &[&Self::Buffer],
+        push_constants: &[u32],
+        group_count: u32,
+    ) -> Result<(), HalError> {
+        use crate::compute::{payload_word, Shader, GROUP_SIZE};
+        let words = |buffer: &MockBuffer| {
+            std::slice::from_raw_parts_mut(buffer.ptr as *mut u32, (buffer.size / 4) as usize)
+        };
+        let (seed, first_word, word_count) = (push_constants[0], push_constants[1], push_constants[2]);
+        let end = first_word.saturating_add(group_count * GROUP_SIZE).min(word_count);
+        let data = words(buffers[0]);
+        for index in first_word..end {
+            let expected = payload_word(index, seed);
+            match shader {
+                Shader::FillPayload => data[index as usize] = u32::to_le(expected),
+                Shader::CheckPayload => {
+                    if u32::from_le(data[index as usize]) != expected {
+                        let result = words(buffers[1]);
+                        result[0] = u32::to_le(u32::from_le(result[0]) + 1);
+                        result[1] = u32::to_le(u32::from_le(result[1]).min(index));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn render(
+        &self,
+        image: &Self::Image,
+        _format: hal::format::Format,
+        _extent: hal::image::Extent,
+        scene: &crate::render::Scene,
+    ) -> Result<(), HalError> {
+        // Draws the reference, through the footprint of the single color plane
+        let footprint = &image.footprints[0][0];
+        for (x, y, pixel) in scene.reference().enumerate_pixels() {
+            let offset = footprint.slice.start + y as u64 * footprint.row_pitch + x as u64 * 4;
+            std::ptr::copy_nonoverlapping(pixel.0.as_ptr(), image.ptrs[0].add(offset as usize), 4);
+        }
+        Ok(())
+    }
+
+    unsafe fn destroy_buffer(&self, _buffer: Self::Buffer) {
+        self.track(|live| live.buffers -= 1);
+    }
+
+    unsafe fn destroy_image(&self, _image: Self::Image) {
+        self.track(|live| live.images -= 1);
+    }
+
+    unsafe fn free_memory(&self, memory: Self::Memory) {
+        memory.storage.release(memory.size);
+        if let Some(shadow) = memory.shadow {
+            shadow.release(memory.size);
+        }
+        self.track(|live| live.memories -= 1);
+    }
+
+    fn wait_idle(&self) -> Result<(), hal::device::OutOfMemory> {
+        Ok(())
+    }
+}
+
+/// The memory type a resource gets allocated from, the first one of `type_mask`.
+fn memory_type(type_mask: u32) -> Result<u32, ExternalResourceError> {
+    match type_mask & MEMORY_TYPES_MASK {
+        0 => Err(ExternalResourceError::NoValidMemoryTypeId),
+        type_mask => Ok(type_mask.trailing_zeros()),
+    }
+}
+
+/// The inode of the file `fd` refers to, the same for its duplicates.
+fn inode(fd: RawFd) -> Option<u64> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    match unsafe { libc::fstat(fd, &mut stat) } {
+        0 => Some(stat.st_ino as u64),
+        _ => None,
+    }
+}
+
+/// Index of the plane addressed by `aspects`, as a format or a memory plane.
+fn plane_index(aspects: hal::format::Aspects) -> usize {
+    use hal::format::Aspects;
+    [
+        Aspects::PLANE_1 | Aspects::MEMORY_PLANE_1,
+        Aspects::PLANE_2 | Aspects::MEMORY_PLANE_2,
+        Aspects::MEMORY_PLANE_3,
+    ]
+    .iter()
+    .position(|&plane_aspects| plane_aspects.intersects(aspects))
+    .map_or(0, |index| index + 1)
+}
+
+/// Flushed and invalidated segments must be aligned like on a real device.
+fn check_atom_alignment(memory: &MockMemory, segment: hal::memory::Segment) {
+    assert_eq!(segment.offset % NON_COHERENT_ATOM_SIZE, 0, "Unaligned offset of {:?}", segment);
+    if let Some(size) = segment.size {
+        assert!(
+            size % NON_COHERENT_ATOM_SIZE == 0 || segment.offset + size == memory.size,
+            "Unaligned size of {:?}",
+            segment
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExportedMemory, ExternalResource, Resource, RunConfig, TestCase, TestResult, Tests};
+    use hal::format::AsFormat;
+
+    const HANDLE_TYPES: [&str; 3] = ["OPAQUE_FD", "DMA_BUF", "HOST_ALLOCATION"];
+
+    /// The built-in buffer and linear image cases of `HANDLE_TYPES`.
+    fn test_cases() -> Vec<TestCase> {
+        crate::buffer_test_cases()
+            .into_iter()
+            .chain(crate::image_parameters_test_cases(hal::format::Rgba8Srgb::SELF))
+            .filter(|test_case| HANDLE_TYPES.contains(&test_case.name.as_str()))
+            .collect()
+    }
+
+    fn run_tests(device: &MockDevice) -> Vec<Tests> {
+        crate::run_tests(device, &test_cases(), &RunConfig::default())
+    }
+
+
+    /// A linear image with `drm_modifier`, on the coherent memory type.
+    fn create_drm_image(
+        device: &MockDevice,
+        format: hal::format::Format,
+        drm_modifier: u64,
+    ) -> (crate::Parameters, ExternalResource<MockDevice>) {
+        let parameters = crate::image_parameters(
+            ExternalImageMemoryType::DmaBuf(vec![hal::format::DrmModifier::from(drm_modifier)]),
+            format,
+        );
+        let (image, memory) = match parameters.clone() {
+            crate::Parameters::Image {
+                external_memory_type,
+                kind,
+                mip_levels,
+                format,
+                tiling,
+                usage,
+                sparse,
+                view_caps,
+            } => unsafe {
+                device.create_allocate_external_image(
+                    external_memory_type,
+                    kind,
+                    mip_levels,
+                    format,
+                    tiling,
+                    usage,
+                    sparse,
+                    view_caps,
+                    1,
+                )
+            }
+            .unwrap(),
+            crate::Parameters::Buffer { .. } => unreachable!(),
+        };
+        (parameters, ExternalResource::new(device, Resource::Image(image), memory))
+    }
+
+    #[test]
+    fn every_stage_succeeds() {
+        let _fds = crate::lock_fds();
+        let device = MockDevice::new();
+        let tests = run_tests(&device);
+        // Mapped on the coherent and the non-coherent memory types
+        assert_eq!(tests.len(), 2 * HANDLE_TYPES.len() * 2);
+
+        for tests in &tests {
+            let image = matches!(tests.parameters, crate::Parameters::Image { .. });
+            for &(stage, result) in tests.stages().iter() {
+                let skipped = match stage {
+                    "wrong_drm_modifier_import" => true,
+                    "footprint_check" => !image,
+                    _ => false,
+                };
+                match result {
+                    TestResult::Success => assert!(!skipped, "{} {}: {:?}", tests.name, stage, result),
+                    TestResult::Skipped(_) => assert!(skipped, "{} {}: {:?}", tests.name, stage, result),
+                    TestResult::Failed(_) => panic!("{} {}: {:?}", tests.name, stage, result),
+                }
+            }
+        }
+        assert!(device.live_objects().is_empty(), "{:?}", device.live_objects());
+    }
+
+    #[test]
+    fn corrupted_import_fails_data_check() {
+        let _fds = crate::lock_fds();
+        let device = MockDevice::new();
+        let test_case = crate::buffer_test_cases().remove(0);
+        let test_case = TestCase { data: test_case.data.with_seed(), ..test_case };
+        let external_memory_type = test_case.parameters.external_memory_type();
+        let size = test_case.data.payload_size().unwrap();
+        // The coherent memory type
+        let memory_types = 1;
+
+        let (buffer, memory) = unsafe {
+            device.create_allocate_external_buffer(
+                ExternalBufferMemoryType::OpaqueFd,
+                hal::buffer::Usage::VERTEX,
+                hal::memory::SparseFlags::empty(),
+                memory_types,
+                size,
+            )
+        }
+        .unwrap();
+        let mut exportable = ExternalResource::new(&device, Resource::Buffer(buffer), memory);
+        crate::write_data(&device, &test_case, &mut exportable).unwrap();
+        let mut exported =
+            ExportedMemory::new(unsafe { device.export_memory(external_memory_type, exportable.memory()) }.unwrap());
+        let mut imported = crate::import_resource(
+            &device,
+            &test_case.parameters,
+            exported.take().unwrap(),
+            None,
+            memory_types,
+            size,
+        )
+        .unwrap();
+        exported.release();
+
+        let memory = imported.memory_mut().unwrap();
+        unsafe {
+            let mapping = device.map_memory(memory, hal::memory::Segment::ALL).unwrap();
+            *mapping = !*mapping;
+            device.unmap_memory(memory);
+        }
+        let checked = crate::check_data(&device, &test_case, &mut imported, None);
+        assert!(crate::data_check_result(&test_case, checked, None).is_failed());
+    }
+
+    #[test]
+    fn wrong_drm_modifier_import_is_rejected() {
+        let _fds = crate::lock_fds();
+        let device = MockDevice::new();
+        let fds_before = crate::fault_injection::open_fds();
+        let test_case = crate::drm_modifier_test_cases(&device, &[hal::format::Rgba8Srgb::SELF]).remove(0);
+
+        for tests in crate::run_test(&test_case, &device, &RunConfig::default()) {
+            assert!(matches!(tests.wrong_drm_modifier_import, TestResult::Success), "{:?}", tests);
+            assert!(!tests.failed(), "{:?}", tests);
+        }
+        assert!(device.live_objects().is_empty(), "{:?}", device.live_objects());
+        assert_eq!(crate::fault_injection::open_fds(), fds_before);
+    }
+
+    #[test]
+    fn import_with_another_drm_modifier_fails_cleanly() {
+        let _fds = crate::lock_fds();
+        let device = MockDevice::new();
+        let format = hal::format::Rgba8Srgb::SELF;
+        let [linear, x_tiled, _] = drm_modifiers();
+        let (parameters, exportable) = create_drm_image(&device, format, linear.drm_modifier);
+        let live_objects = device.live_objects();
+
+        let mut drm_properties = crate::drm_import_properties(&device, format, exportable.image().unwrap()).unwrap();
+        drm_properties.drm_modifier = hal::format::DrmModifier::from(x_tiled.drm_modifier);
+        let external_memory = unsafe { device.export_memory(ExternalMemoryType::DmaBuf, exportable.memory()) }.unwrap();
+        let mut exported = ExportedMemory::new(external_memory);
+        let fd = exported.fd().unwrap();
+        let imported =
+            crate::import_resource(&device, &parameters, exported.take().unwrap(), Some(drm_properties), 1, 0);
+
+        assert!(matches!(imported, Err(ExternalResourceError::InvalidExternalHandle)));
+        assert_eq!(device.live_objects(), live_objects);
+        // The failed import left the handle to the caller
+        assert!(unsafe { libc::fcntl(fd, libc::F_GETFD) } >= 0);
+    }
+
+    /// Imports the memory of `exportable` with `drm_properties` and compares the
+    /// footprints of the memory planes of the exporter and the importer.
+    fn import_footprint_check(
+        device: &MockDevice,
+        parameters: &crate::Parameters,
+        exportable: &ExternalResource<MockDevice>,
+        drm_properties: hal::image::DrmFormatImageProperties,
+    ) -> TestResult {
+        let aspects = crate::MEMORY_PLANE_ASPECTS[..drm_properties.plane_layouts.len()].to_vec();
+        let external_memory = unsafe { device.export_memory(ExternalMemoryType::DmaBuf, exportable.memory()) }.unwrap();
+        let mut exported = ExportedMemory::new(external_memory);
+        let imported =
+            crate::import_resource(device, parameters, exported.take().unwrap(), Some(drm_properties), 1, 0).unwrap();
+        exported.release();
+        crate::footprint_check(device, exportable, &imported, &aspects, None)
+    }
+
+    /// Checks the footprint of each memory plane of a linear `format` image, then
+    /// that the importer sees the same ones, unless its layout is shifted.
+    fn check_memory_plane_footprints(format: hal::format::Format) {
+        let _fds = crate::lock_fds();
+        let device = MockDevice::new();
+        let [linear, ..] = drm_modifiers();
+        let (parameters, exportable) = create_drm_image(&device, format, linear.drm_modifier);
+        let drm_properties = crate::drm_import_properties(&device, format, exportable.image().unwrap()).unwrap();
+
+        let planes = crate::image_data::plane_descs(format);
+        let layouts = &drm_properties.plane_layouts;
+        assert_eq!(layouts.len(), planes.len());
+        for (index, (layout, plane)) in layouts.iter().zip(&planes).enumerate() {
+            let row_len = (crate::WIDTH / plane.width_divisor * plane.texel_size) as u64;
+            let height = (crate::HEIGHT / plane.height_divisor) as u64;
+            let row_pitch = (row_len + ROW_PITCH_ALIGNMENT - 1) / ROW_PITCH_ALIGNMENT * ROW_PITCH_ALIGNMENT;
+            assert_eq!(layout.row_pitch, row_pitch, "Plane {}: {:?}", index, layouts);
+            assert_eq!(layout.slice.end - layout.slice.start, row_pitch * height, "Plane {}: {:?}", index, layouts);
+            if index > 0 {
+                assert!(layout.slice.start >= layouts[index - 1].slice.end, "Plane {}: {:?}", index, layouts);
+            }
+        }
+
+        let matching = import_footprint_check(&device, &parameters, &exportable, drm_properties.clone());
+        assert!(matches!(matching, TestResult::Success), "{:?}", matching);
+
+        let mut shifted = drm_properties;
+        let slice = &mut shifted.plane_layouts[0].slice;
+        *slice = slice.start + ROW_PITCH_ALIGNMENT..slice.end + ROW_PITCH_ALIGNMENT;
+        assert!(import_footprint_check(&device, &parameters, &exportable, shifted).is_failed());
+        drop(exportable);
+        assert!(device.live_objects().is_empty(), "{:?}", device.live_objects());
+    }
+
+    #[test]
+    fn nv12_memory_plane_footprints() {
+        check_memory_plane_footprints(hal::format::Format::G8B8r82plane420Unorm);
+    }
+
+    #[test]
+    fn i420_memory_plane_footprints() {
+        check_memory_plane_footprints(hal::format::Format::G8B8R83plane420Unorm);
+    }
+
+    #[test]
+    fn drm_modifier_test_cases_follow_the_modifiers() {
+        let device = MockDevice::new();
+        let [linear, _, y_tiled_ccs] = drm_modifiers();
+        let rgba = hal::format::Rgba8Srgb::SELF;
+        let nv12 = hal::format::Format::G8B8r82plane420Unorm;
+        let test_cases = crate::drm_modifier_test_cases(&device, &[rgba, nv12]);
+
+        // The X tiled modifier allows none of the usages of the test cases
+        let expected = [
+            (rgba, linear.drm_modifier, crate::Verification::Map),
+            (rgba, y_tiled_ccs.drm_modifier, crate::Verification::Unchecked),
+            (nv12, linear.drm_modifier, crate::Verification::Map),
+            (nv12, y_tiled_ccs.drm_modifier, crate::Verification::Unchecked),
+        ];
+        let names: Vec<_> = test_cases.iter().map(|test_case| &test_case.name).collect();
+        assert_eq!(test_cases.len(), expected.len(), "{:?}", names);
+        for (test_case, &(expected_format, expected_modifier, expected_verification)) in test_cases.iter().zip(&expected)
+        {
+            match &test_case.parameters {
+                crate::Parameters::Image {
+                    external_memory_type: ExternalImageMemoryType::DmaBuf(drm_modifiers),
+                    format,
+                    usage,
+                    ..
+                } => {
+                    assert_eq!(*format, expected_format, "{}", test_case.name);
+                    assert_eq!(drm_modifiers.len(), 1, "{}", test_case.name);
+                    assert_eq!(drm_modifiers[0], hal::format::DrmModifier::from(expected_modifier), "{}", test_case.name);
+                    assert_eq!(*usage, hal::image::Usage::TRANSFER_DST | hal::image::Usage::SAMPLED, "{}", test_case.name);
+                }
+                _ => panic!("{}: not a DMA_BUF image", test_case.name),
+            }
+            assert_eq!(test_case.verification, expected_verification, "{}", test_case.name);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Payloads shared through buffers.
+//!
+//! A payload is generated from a seeded PRNG or a counter, or read from a file, and
+//! streamed in chunks, so it can be larger than the host memory.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::DataTest;
+
+/// Size of the payloads that do not set one and are not read from a file.
+pub const DEFAULT_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Pattern {
+    /// A splitmix64 stream. Without a seed, every run picks a new one.
+    Random { seed: Option<u64> },
+    /// Little endian `u32`s counting from 0.
+    Counter,
+    /// The content of a file, repeated to fill the payload.
+    File(PathBuf),
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self::Random { seed: None }
+    }
+}
+
+impl DataTest {
+    /// Size of the payload in bytes.
+    pub fn payload_size(&self) -> io::Result<u64> {
+        match (self.size, &self.pattern) {
+            (Some(size), _) => Ok(size),
+            (None, Pattern::File(path)) => Ok(std::fs::metadata(path)?.len()),
+            (None, _) => Ok(DEFAULT_SIZE),
+        }
+    }
+
+    /// The seed of a random payload.
+    pub fn seed(&self) -> Option<u64> {
+        match self.pattern {
+            Pattern::Random { seed } => seed,
+            _ => None,
+        }
+    }
+
+    /// The same payload, with a new seed if it is random without one.
+    pub fn with_seed(&self) -> Self {
+        let mut data = self.clone();
+        if let Pattern::Random { seed } = &mut data.pattern {
+            if seed.is_none() {
+                *seed = Some(new_seed());
+            }
+        }
+        data
+    }
+
+    /// Streams the `len` bytes of the payload. A random payload must have a seed.
+    pub fn reader(&self) -> io::Result<Box<dyn Read>> {
+        let len = self.payload_size()?;
+        let reader: Box<dyn Read> = match &self.pattern {
+            Pattern::Random { seed: Some(seed) } => {
+                let mut state = *seed;
+                Box::new(Words::new(move || {
+                    // splitmix64
+                    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    z ^ (z >> 31)
+                }))
+            }
+            Pattern::Random { seed: None } => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "The random payload has no seed"))
+            }
+            Pattern::Counter => {
+                let mut counter = 0u64;
+                Box::new(Words::new(move || {
+                    let word = counter | ((counter + 1) << 32);
+                    counter = (counter + 2) & 0xffff_ffff;
+                    word
+                }))
+            }
+            Pattern::File(path) => {
+                let file = std::fs::File::open(path)?;
+                if file.metadata()?.len() == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("The payload file {} is empty", path.display()),
+                    ));
+                }
+                Box::new(Repeat(file))
+            }
+        };
+        Ok(Box::new(reader.take(len)))
+    }
+}
+
+/// A seed that differs between runs and processes.
+fn new_seed() -> u64 {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    time ^ ((std::process::id() as u64) << 32)
+}
+
+/// Endless stream of the little endian bytes of the words returned by `next`.
+struct Words<F> {
+    next: F,
+    word: [u8; 8],
+    position: usize,
+}
+
+impl<F: FnMut() -> u64> Words<F> {
+    fn new(next: F) -> Self {
+        Self {
+            next,
+            word: [0; 8],
+            position: 8,
+        }
+    }
+}
+
+impl<F: FnMut() -> u64> Read for Words<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.position == self.word.len() {
+                self.word = (self.next)().to_le_bytes();
+                self.position = 0;
+            }
+            let len = (self.word.len() - self.position).min(buf.len() - written);
+            buf[written..written + len].copy_from_slice(&self.word[self.position..self.position + len]);
+            self.position += len;
+            written += len;
+        }
+        Ok(written)
+    }
+}
+
+/// Reads a non-empty file over and over.
+struct Repeat(std::fs::File);
+
+impl Read for Repeat {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf)? {
+            0 if !buf.is_empty() => {
+                self.0.seek(SeekFrom::Start(0))?;
+                self.0.read(buf)
+            }
+            len => Ok(len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(data: &DataTest) -> Vec<u8> {
+        let mut payload = Vec::new();
+        data.reader().unwrap().read_to_end(&mut payload).unwrap();
+        payload
+    }
+
+    fn random(seed: Option<u64>, size: u64) -> DataTest {
+        DataTest {
+            size: Some(size),
+            pattern: Pattern::Random { seed },
+        }
+    }
+
+    #[test]
+    fn random_payloads_follow_the_seed() {
+        assert_eq!(payload(&random(Some(1), 1000)), payload(&random(Some(1), 1000)));
+        assert_ne!(payload(&random(Some(1), 1000)), payload(&random(Some(2), 1000)));
+
+        let seeded = random(None, 1000).with_seed();
+        assert!(seeded.seed().is_some());
+        assert_eq!(seeded.with_seed(), seeded);
+        assert_eq!(payload(&seeded), payload(&seeded));
+        assert!(random(None, 1000).reader().is_err());
+    }
+
+    #[test]
+    fn payloads_have_their_size() {
+        for &size in &[0, 1, 7, 8, 13, 4096, 4099] {
+            assert_eq!(payload(&random(Some(1), size)).len() as u64, size);
+            let counter = DataTest {
+                size: Some(size),
+                pattern: Pattern::Counter,
+            };
+            assert_eq!(payload(&counter).len() as u64, size);
+        }
+        let default_size = DataTest {
+            size: None,
+            pattern: Pattern::Counter,
+        };
+        assert_eq!(payload(&default_size).len() as u64, DEFAULT_SIZE);
+    }
+
+    #[test]
+    fn counter_counts_u32s() {
+        let counter = DataTest {
+            size: Some(13),
+            pattern: Pattern::Counter,
+        };
+        assert_eq!(payload(&counter), [0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn file_payloads_repeat_the_file() {
+        let _fds = crate::lock_fds();
+        let path = std::env::temp_dir().join(format!("gfx_external_memory_test_payload_{}", std::process::id()));
+        std::fs::write(&path, b"abcde").unwrap();
+        let file = |size| DataTest {
+            size,
+            pattern: Pattern::File(path.clone()),
+        };
+        let payloads = (payload(&file(None)), payload(&file(Some(13))), payload(&file(Some(3))));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(payloads.0, b"abcde");
+        assert_eq!(payloads.1, b"abcdeabcdeabc");
+        assert_eq!(payloads.2, b"abc");
+    }
+}
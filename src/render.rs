@@ -0,0 +1,150 @@
+//! Images rendered to by the exporter, the way a client renders to the buffers
+//! it hands to a compositor, and copied out by the importer.
+//!
+//! The exporter clears its image and draws a triangle on it in a render pass
+//! with `triangle.vert` and `triangle.frag`. The importer copies its image to a
+//! staging buffer and compares it with a reference drawn on the CPU. The
+//! vertices are on pixel corners and no pixel center lies on an edge, so every
+//! rasterizer covers the same pixels and the comparison can be exact.
+
+use gfx_hal as hal;
+use hal::external_memory::ExternalImageMemoryType;
+
+use crate::image_data::ImageContent;
+use crate::{ExternalResource, HalError, MemoryFilter, Parameters, TestCase, TestDevice, Verification};
+
+/// Size of the rendered images.
+const SIZE: u32 = 256;
+
+/// The color the image is cleared to, as `Rgba8Unorm` texels.
+pub const CLEAR_COLOR: [u8; 4] = [0, 0, 255, 255];
+/// The color `triangle.frag` writes.
+pub const TRIANGLE_COLOR: [u8; 4] = [255, 255, 0, 255];
+
+const TRIANGLE_VERT: &[u8] = include_bytes!("../shaders/triangle.vert.spv");
+const TRIANGLE_FRAG: &[u8] = include_bytes!("../shaders/triangle.frag.spv");
+
+/// The shaders drawing the triangle. The vertex shader reads the vertices from
+/// its push constants, as `[x, y]` pairs in normalized device coordinates.
+pub fn spirv() -> (Vec<u32>, Vec<u32>) {
+    (words(TRIANGLE_VERT), words(TRIANGLE_FRAG))
+}
+
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect()
+}
+
+/// A triangle on an image of `extent`, with its vertices on pixel corners.
+#[derive(Debug, Clone, Copy)]
+pub struct Scene {
+    pub extent: hal::image::Extent,
+    /// In pixels, from the top left corner.
+    pub vertices: [[u32; 2]; 3],
+}
+
+impl Scene {
+    /// A right triangle covering most of the image. The hypotenuse gets a slope
+    /// that keeps it off every pixel center.
+    pub fn new(extent: hal::image::Extent) -> Self {
+        let (left, top) = (extent.width / 8, extent.height / 8);
+        let right = extent.width - left;
+        let mut bottom = extent.height - top;
+        // A pixel center (x + 0.5, y + 0.5) is on the hypotenuse only if the
+        // width and height of the triangle add up to an even number
+        if (right - left + bottom - top) % 2 == 0 {
+            bottom -= 1;
+        }
+        Self {
+            extent,
+            vertices: [[left, top], [right, top], [left, bottom]],
+        }
+    }
+
+    /// The vertices in normalized device coordinates, as the vertex shader reads
+    /// them.
+    pub fn push_constants(&self) -> [u32; 6] {
+        let mut push_constants = [0; 6];
+        for (index, &[x, y]) in self.vertices.iter().enumerate() {
+            push_constants[index * 2] = (2.0 * x as f32 / self.extent.width as f32 - 1.0).to_bits();
+            push_constants[index * 2 + 1] = (2.0 * y as f32 / self.extent.height as f32 - 1.0).to_bits();
+        }
+        push_constants
+    }
+
+    /// Whether the center of pixel `x`, `y` is inside the triangle, whatever its
+    /// winding. Coordinates are doubled to keep the centers integers.
+    pub fn covers(&self, x: u32, y: u32) -> bool {
+        let center = [2 * x as i64 + 1, 2 * y as i64 + 1];
+        let vertices: Vec<[i64; 2]> =
+            self.vertices.iter().map(|&[x, y]| [2 * x as i64, 2 * y as i64]).collect();
+        let sides: Vec<i64> = (0..3)
+            .map(|index| {
+                let (a, b) = (vertices[index], vertices[(index + 1) % 3]);
+                (b[0] - a[0]) * (center[1] - a[1]) - (b[1] - a[1]) * (center[0] - a[0])
+            })
+            .collect();
+        sides.iter().all(|&side| side > 0) || sides.iter().all(|&side| side < 0)
+    }
+
+    /// The texels the exporter renders.
+    pub fn reference(&self) -> image::RgbaImage {
+        image::RgbaImage::from_fn(self.extent.width, self.extent.height, |x, y| {
+            image::Rgba(if self.covers(x, y) { TRIANGLE_COLOR } else { CLEAR_COLOR })
+        })
+    }
+}
+
+/// Renders the scene on the image of `resource`.
+pub fn write_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &ExternalResource<D>,
+) -> Result<(), HalError> {
+    let (kind, format) = match test_case.parameters {
+        Parameters::Image { kind, format, .. } => (kind, format),
+        Parameters::Buffer { .. } => return Err(String::from("Only images can be rendered to").into()),
+    };
+    let extent = kind.extent();
+    unsafe { device.render(resource.image()?, format, extent, &Scene::new(extent)) }
+}
+
+/// `Rgba8Unorm` color attachment cases for the handle types shared between
+/// devices, on device local memory types, rendered to by the exporter and copied
+/// out by the importer. DmaBuf images are linear, as a compositor importing them
+/// without DRM format modifiers expects.
+pub fn render_test_cases() -> Vec<TestCase> {
+    let mut external_memory_types = Vec::new();
+    #[cfg(unix)]
+    external_memory_types.push(("OPAQUE_FD", ExternalImageMemoryType::OpaqueFd, hal::image::Tiling::Optimal));
+    #[cfg(windows)]
+    external_memory_types.push(("OPAQUE_WIN32", ExternalImageMemoryType::OpaqueWin32, hal::image::Tiling::Optimal));
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    external_memory_types.push(("DMA_BUF", ExternalImageMemoryType::DmaBuf(Vec::new()), hal::image::Tiling::Linear));
+
+    external_memory_types
+        .into_iter()
+        .map(|(name, external_memory_type, tiling)| TestCase {
+            image_content: ImageContent::Triangle,
+            memory_filter: MemoryFilter::DEVICE_LOCAL,
+            verification: Verification::Render,
+            ..TestCase::new(
+                format!("{} RENDER", name),
+                Parameters::Image {
+                    external_memory_type,
+                    kind: hal::image::Kind::D2(SIZE, SIZE, 1, 1),
+                    mip_levels: 1,
+                    format: hal::format::Format::Rgba8Unorm,
+                    tiling,
+                    usage: hal::image::Usage::COLOR_ATTACHMENT
+                        | hal::image::Usage::TRANSFER_SRC
+                        | hal::image::Usage::SAMPLED,
+                    sparse: hal::memory::SparseFlags::empty(),
+                    view_caps: hal::image::ViewCapabilities::empty(),
+                },
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,363 @@
+//! Machine readable reports of a test run.
+
+use gfx_hal as hal;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::{MemoryTypeInfo, Parameters, TestResult, Tests};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    /// The `Debug` output of `Tests`, meant to be pasted in GitHub issues.
+    Text,
+    Json,
+    JUnit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdapterReport {
+    pub name: String,
+    pub vendor: usize,
+    pub device: usize,
+    pub device_type: String,
+}
+
+impl From<hal::adapter::AdapterInfo> for AdapterReport {
+    fn from(info: hal::adapter::AdapterInfo) -> Self {
+        Self {
+            name: info.name,
+            vendor: info.vendor,
+            device: info.device,
+            device_type: format!("{:?}", info.device_type),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryTypeReport {
+    pub index: usize,
+    pub properties: String,
+    pub heap_index: usize,
+    pub heap_flags: String,
+    pub heap_size: u64,
+}
+
+impl From<&MemoryTypeInfo> for MemoryTypeReport {
+    fn from(memory_type: &MemoryTypeInfo) -> Self {
+        Self {
+            index: memory_type.index,
+            properties: format!("{:?}", memory_type.properties),
+            heap_index: memory_type.heap_index,
+            heap_flags: format!("{:?}", memory_type.heap_flags),
+            heap_size: memory_type.heap_size,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageReport {
+    pub name: &'static str,
+    /// `success`, `failed` or `skipped`.
+    pub result: &'static str,
+    /// Why the stage has been skipped.
+    pub reason: Option<String>,
+    /// The hal call that failed.
+    pub call: Option<&'static str>,
+    pub error: Option<String>,
+    /// The queried `ExternalMemoryProperties` of a failed stage.
+    pub properties: Option<String>,
+}
+
+impl StageReport {
+    fn new(name: &'static str, result: &TestResult) -> Self {
+        let mut report = Self {
+            name,
+            result: "success",
+            reason: None,
+            call: None,
+            error: None,
+            properties: None,
+        };
+        match result {
+            TestResult::Success => {}
+            TestResult::Skipped(reason) => {
+                report.result = "skipped";
+                report.reason = Some(reason.to_string());
+            }
+            TestResult::Failed(error) => {
+                report.result = "failed";
+                report.call = Some(error.call);
+                report.error = Some(error.error.to_string());
+                report.properties = error.properties.map(|properties| format!("{:?}", properties));
+            }
+        }
+        report
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestReport<'a> {
+    pub name: &'a str,
+    pub resource: &'static str,
+    pub parameters: &'a Parameters,
+    pub memory_type: Option<MemoryTypeReport>,
+    /// Seed of the random payload, to reproduce a failure with `--seed`.
+    pub seed: Option<u64>,
+    pub stages: Vec<StageReport>,
+}
+
+impl<'a> From<&'a Tests> for TestReport<'a> {
+    fn from(tests: &'a Tests) -> Self {
+        Self {
+            name: &tests.name,
+            resource: tests.parameters.resource_name(),
+            parameters: &tests.parameters,
+            memory_type: tests.memory_type.as_ref().map(Into::into),
+            seed: tests.seed,
+            stages: tests
+                .stages()
+                .iter()
+                .map(|(name, result)| StageReport::new(name, result))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub adapter: AdapterReport,
+    pub tests: Vec<TestReport<'a>>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(adapter: hal::adapter::AdapterInfo, tests: &'a [Tests]) -> Self {
+        Self {
+            adapter: adapter.into(),
+            tests: tests.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+pub fn write_report(
+    writer: &mut dyn Write,
+    format: ReportFormat,
+    report: &Report,
+    tests: &[Tests],
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Text => write_text(writer, tests),
+        ReportFormat::Json => write_json(writer, report),
+        ReportFormat::JUnit => write_junit(writer, report),
+    }
+}
+
+pub fn write_text(writer: &mut dyn Write, tests: &[Tests]) -> io::Result<()> {
+    let mut resource = "";
+    for tests in tests {
+        if tests.parameters.resource_name() != resource {
+            resource = tests.parameters.resource_name();
+            writeln!(writer, "Resource: {}", resource)?;
+        }
+        writeln!(writer, "{:#?}", tests)?;
+    }
+    Ok(())
+}
+
+pub fn write_json(writer: &mut dyn Write, report: &Report) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, report)?;
+    writeln!(writer)
+}
+
+/// Writes one `testsuite` per test, with one `testcase` per stage.
+pub fn write_junit(writer: &mut dyn Write, report: &Report) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuites name="{}">"#,
+        escape_xml(&report.adapter.name)
+    )?;
+    for test in &report.tests {
+        let count = |result: &str| {
+            test.stages
+                .iter()
+                .filter(|stage| stage.result == result)
+                .count()
+        };
+        writeln!(
+            writer,
+            r#"  <testsuite name="{}.{}" tests="{}" failures="{}" skipped="{}">"#,
+            test.resource,
+            escape_xml(test.name),
+            test.stages.len(),
+            count("failed"),
+            count("skipped"),
+        )?;
+        let mut properties = Vec::new();
+        if let Some(memory_type) = &test.memory_type {
+            properties.push(("memory_type", memory_type.index.to_string()));
+            properties.push(("memory_properties", memory_type.properties.clone()));
+            properties.push(("heap", memory_type.heap_index.to_string()));
+            properties.push(("heap_flags", memory_type.heap_flags.clone()));
+        }
+        if let Some(seed) = test.seed {
+            properties.push(("seed", seed.to_string()));
+        }
+        if !properties.is_empty() {
+            writeln!(writer, "    <properties>")?;
+            for (name, value) in properties {
+                writeln!(
+                    writer,
+                    r#"      <property name="{}" value="{}"/>"#,
+                    name,
+                    escape_xml(&value)
+                )?;
+            }
+            writeln!(writer, "    </properties>")?;
+        }
+        for stage in &test.stages {
+            write!(
+                writer,
+                r#"    <testcase classname="{}.{}" name="{}""#,
+                test.resource,
+                escape_xml(test.name),
+                stage.name
+            )?;
+            match stage.result {
+                "failed" => {
+                    writeln!(writer, ">")?;
+                    writeln!(
+                        writer,
+                        r#"      <failure message="{}" type="{}">{}</failure>"#,
+                        escape_xml(stage.error.as_deref().unwrap_or("failed")),
+                        stage.call.unwrap_or_default(),
+                        escape_xml(stage.properties.as_deref().unwrap_or_default())
+                    )?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+                "skipped" => {
+                    writeln!(writer, ">")?;
+                    writeln!(
+                        writer,
+                        r#"      <skipped message="{}"/>"#,
+                        escape_xml(stage.reason.as_deref().unwrap_or_default())
+                    )?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+                _ => writeln!(writer, "/>")?,
+            }
+        }
+        writeln!(writer, "  </testsuite>")?;
+    }
+    writeln!(writer, "</testsuites>")
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HalError, SkipReason, TestError};
+    use hal::format::AsFormat;
+
+    fn adapter() -> hal::adapter::AdapterInfo {
+        hal::adapter::AdapterInfo {
+            name: "GPU <&> \"1\"".into(),
+            vendor: 1,
+            device: 2,
+            device_type: hal::adapter::DeviceType::DiscreteGpu,
+        }
+    }
+
+    /// A buffer test succeeding up to its data check, and an image test failing
+    /// with an error to escape.
+    fn tests() -> Vec<Tests> {
+        let buffer = crate::buffer_test_cases().remove(0);
+        let mut succeeding = Tests::new(buffer.name, buffer.parameters);
+        succeeding.seed = Some(42);
+        succeeding.create_allocate_external_resource = TestResult::Success;
+        succeeding.write_data = TestResult::Success;
+        succeeding.export_memory = TestResult::Success;
+        succeeding.import_external_resource = TestResult::Success;
+        succeeding.data_check = TestResult::Success;
+
+        let mut failing = Tests::new(
+            "OPAQUE_FD <'&'>".into(),
+            crate::image_parameters(
+                hal::external_memory::ExternalImageMemoryType::OpaqueFd,
+                hal::format::Rgba8Srgb::SELF,
+            ),
+        );
+        failing.create_allocate_external_resource = TestResult::Failed(TestError {
+            call: "create_allocate_external_image",
+            error: HalError::Other("Expected <1> & got \"2\"".into()),
+            properties: None,
+        });
+        failing.write_data = TestResult::Skipped(SkipReason::NotReached);
+        vec![succeeding, failing]
+    }
+
+    #[test]
+    fn escape_xml_escapes_markup() {
+        assert_eq!(escape_xml("plain text"), "plain text");
+        assert_eq!(
+            escape_xml(r#"<a href="x">'b' & c</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&apos;b&apos; &amp; c&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn json_report_parses_back() {
+        let tests = tests();
+        let mut json = Vec::new();
+        write_json(&mut json, &Report::new(adapter(), &tests)).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["adapter"]["name"], adapter().name.as_str());
+        let reports = json["tests"].as_array().unwrap();
+        assert_eq!(reports.len(), tests.len());
+        for (report, tests) in reports.iter().zip(&tests) {
+            assert_eq!(report["name"], tests.name.as_str());
+            assert_eq!(report["stages"].as_array().unwrap().len(), tests.stages().len());
+        }
+        assert_eq!(reports[0]["seed"], 42);
+        let failed = &reports[1]["stages"][0];
+        assert_eq!(failed["result"], "failed");
+        assert_eq!(failed["call"], "create_allocate_external_image");
+        assert_eq!(failed["error"], "Expected <1> & got \"2\"");
+    }
+
+    #[test]
+    fn junit_report_parses_back() {
+        let tests = tests();
+        let mut junit = Vec::new();
+        write_junit(&mut junit, &Report::new(adapter(), &tests)).unwrap();
+
+        let junit = String::from_utf8(junit).unwrap();
+        let document = roxmltree::Document::parse(&junit).unwrap();
+        let elements = |tag: &'static str| document.descendants().filter(move |node| node.has_tag_name(tag));
+        assert_eq!(document.root_element().attribute("name"), Some(adapter().name.as_str()));
+        assert_eq!(elements("testsuite").count(), tests.len());
+        assert_eq!(elements("testcase").count(), tests.len() * tests[0].stages().len());
+
+        let failures = elements("failure").collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].attribute("message"), Some("Expected <1> & got \"2\""));
+        assert_eq!(failures[0].attribute("type"), Some("create_allocate_external_image"));
+        let suite = elements("testsuite").nth(1).unwrap();
+        assert_eq!(suite.attribute("name"), Some("Image.OPAQUE_FD <'&'>"));
+        assert_eq!(suite.attribute("failures"), Some("1"));
+    }
+}
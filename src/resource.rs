@@ -0,0 +1,200 @@
+use gfx_hal as hal;
+use crate::TestDevice;
+
+pub enum Resource<T: TestDevice> {
+    Buffer(T::Buffer),
+    Image(T::Image)
+}
+impl<T: TestDevice> Resource<T> {
+    pub fn image(&self)->Result<&T::Image, ResourceError> {
+        match self {
+            Self::Image(image)=>Ok(image),
+            _=>Err(ResourceError::NotAnImage)
+        }
+    }
+    pub fn buffer(&self)->Result<&T::Buffer, ResourceError> {
+        match self {
+            Self::Buffer(buffer)=>Ok(buffer),
+            _=>Err(ResourceError::NotABuffer)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceError {
+    NotAnImage,
+    NotABuffer,
+    /// The memory is kept mapped by `ExternalResource::map`.
+    Mapped,
+}
+
+impl std::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAnImage => f.write_str("The resource is not an image"),
+            Self::NotABuffer => f.write_str("The resource is not a buffer"),
+            Self::Mapped => f.write_str("The memory is kept mapped"),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+/// An exported or imported resource, or a staging buffer, together with the
+/// memory bound to it, or to each plane of a disjoint image.
+///
+/// On drop the memory is unmapped if needed, then the resource is destroyed and
+/// finally the memory is freed.
+/// An imported host allocation points into the memory of its exporter, so it must
+/// be dropped first: declare it after the exporter.
+pub struct ExternalResource<'a, D: TestDevice> {
+    device: &'a D,
+    resource: Option<Resource<D>>,
+    /// A single memory object, unless the resource is a disjoint image.
+    memories: Vec<D::Memory>,
+    mapping: Option<*mut u8>,
+}
+
+impl<'a, D: TestDevice> ExternalResource<'a, D> {
+    pub fn new(device: &'a D, resource: Resource<D>, memory: D::Memory) -> Self {
+        Self::new_disjoint(device, resource, vec![memory])
+    }
+
+    /// A disjoint image, with the memory of each of its planes.
+    pub fn new_disjoint(device: &'a D, resource: Resource<D>, memories: Vec<D::Memory>) -> Self {
+        Self {
+            device,
+            resource: Some(resource),
+            memories,
+            mapping: None,
+        }
+    }
+
+    pub fn resource(&self) -> &Resource<D> {
+        self.resource.as_ref().unwrap()
+    }
+
+    pub fn image(&self) -> Result<&D::Image, ResourceError> {
+        self.resource().image()
+    }
+
+    pub fn buffer(&self) -> Result<&D::Buffer, ResourceError> {
+        self.resource().buffer()
+    }
+
+    /// The memory of the resource, or of the first plane of a disjoint image.
+    pub fn memory(&self) -> &D::Memory {
+        &self.memories[0]
+    }
+
+    /// The memory of each plane of a disjoint image, a single memory otherwise.
+    pub fn memories(&self) -> &[D::Memory] {
+        &self.memories
+    }
+
+    /// Mutable access to the memory, only while it is not kept mapped.
+    pub fn memory_mut(&mut self) -> Option<&mut D::Memory> {
+        match self.mapping {
+            Some(_) => None,
+            None => self.memories.first_mut(),
+        }
+    }
+
+    /// The image together with mutable access to its memory, only while the memory
+    /// is not kept mapped.
+    pub fn image_memory_mut(&mut self) -> Result<(&D::Image, &mut D::Memory), ResourceError> {
+        if self.mapping.is_some() {
+            return Err(ResourceError::Mapped);
+        }
+        let image = self.resource.as_ref().unwrap().image()?;
+        Ok((image, &mut self.memories[0]))
+    }
+
+    /// Maps the whole memory and keeps it mapped until `unmap` or drop.
+    /// This is how host allocations are exported.
+    pub fn map(&mut self) -> Result<*mut u8, hal::device::MapError> {
+        if let Some(mapping) = self.mapping {
+            return Ok(mapping);
+        }
+        let mapping = unsafe {
+            self.device
+                .map_memory(&mut self.memories[0], hal::memory::Segment::ALL)
+        }?;
+        self.mapping = Some(mapping);
+        Ok(mapping)
+    }
+
+    pub fn unmap(&mut self) {
+        if self.mapping.take().is_some() {
+            unsafe { self.device.unmap_memory(&mut self.memories[0]) };
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mapping.is_some()
+    }
+}
+
+impl<'a, D: TestDevice> Drop for ExternalResource<'a, D> {
+    fn drop(&mut self) {
+        self.unmap();
+        unsafe {
+            match self.resource.take() {
+                Some(Resource::Buffer(buffer)) => self.device.destroy_buffer(buffer),
+                Some(Resource::Image(image)) => self.device.destroy_image(image),
+                None => {}
+            }
+            for memory in self.memories.drain(..) {
+                self.device.free_memory(memory);
+            }
+        }
+    }
+}
+
+/// A handle returned by `export_memory`.
+///
+/// The file descriptor it owns is closed on drop, unless `release` is called after
+/// a successful import took the ownership of it.
+pub struct ExportedMemory {
+    memory: Option<hal::external_memory::PlatformMemory>,
+    #[cfg(unix)]
+    fd: Option<std::os::unix::io::RawFd>,
+}
+
+impl ExportedMemory {
+    pub fn new(memory: hal::external_memory::PlatformMemory) -> Self {
+        Self {
+            #[cfg(unix)]
+            fd: crate::platform_memory_fd(&memory),
+            memory: Some(memory),
+        }
+    }
+
+    /// The file descriptor owned by this handle, if any.
+    #[cfg(unix)]
+    pub fn fd(&self) -> Option<std::os::unix::io::RawFd> {
+        self.fd
+    }
+
+    /// Hands the handle over to an import.
+    pub fn take(&mut self) -> Option<hal::external_memory::PlatformMemory> {
+        self.memory.take()
+    }
+
+    /// The handle has been consumed by a successful import.
+    pub fn release(&mut self) {
+        #[cfg(unix)]
+        {
+            self.fd = None;
+        }
+    }
+}
+
+impl Drop for ExportedMemory {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(fd) = self.fd.take() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
@@ -0,0 +1,1164 @@
+use gfx_hal as hal;
+use hal::adapter::{Adapter, PhysicalDevice};
+use hal::command::{BufferImageCopy, CommandBuffer};
+use hal::device::Device;
+use hal::external_memory::*;
+use hal::pool::CommandPool;
+use hal::queue::{Queue, QueueFamilyId, QueueGroup};
+use std::cell::RefCell;
+use std::iter;
+use std::mem::ManuallyDrop;
+
+use crate::HalError;
+
+/// The subset of the physical device and device operations used by `run_test`.
+///
+/// It is implemented for every `gfx_hal::Backend` through `HalDevice` and by the
+/// `mock` module, so the test flow can run without a GPU.
+pub trait TestDevice {
+    type Buffer;
+    type Image;
+    type Memory;
+
+    fn external_buffer_properties(
+        &self,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        external_memory_type: ExternalBufferMemoryType,
+    ) -> ExternalMemoryProperties;
+
+    fn external_image_properties(
+        &self,
+        format: hal::format::Format,
+        dimensions: u8,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        view_caps: hal::image::ViewCapabilities,
+        external_memory_type: ExternalMemoryType,
+    ) -> Result<ExternalMemoryProperties, ExternalImagePropertiesError>;
+
+    fn adapter_info(&self) -> hal::adapter::AdapterInfo;
+    fn memory_properties(&self) -> hal::adapter::MemoryProperties;
+    fn format_properties(&self, format: Option<hal::format::Format>) -> hal::format::Properties;
+    fn min_imported_host_pointer_alignment(&self) -> u64;
+    /// Flushed and invalidated ranges of non-coherent memory must be aligned to it.
+    fn non_coherent_atom_size(&self) -> u64;
+
+    unsafe fn create_allocate_external_buffer(
+        &self,
+        external_memory_type: ExternalBufferMemoryType,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError>;
+
+    unsafe fn import_external_buffer(
+        &self,
+        external_memory: ExternalBufferMemory,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError>;
+
+    unsafe fn create_allocate_external_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError>;
+
+    unsafe fn import_external_image(
+        &self,
+        external_memory: ExternalImageMemory,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError>;
+
+    /// Whether multi-planar images can be disjoint, with each plane bound to its
+    /// own memory object.
+    fn disjoint_images(&self) -> bool;
+
+    /// Creates a disjoint multi-planar image and allocates the memory of each of
+    /// its planes, in the order of the planes. Only called if `disjoint_images`.
+    unsafe fn create_allocate_external_disjoint_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError>;
+
+    /// Imports a disjoint multi-planar image from the memory of each of its
+    /// planes. A failed import takes the ownership of none of the handles. Only
+    /// called if `disjoint_images`.
+    unsafe fn import_external_disjoint_image(
+        &self,
+        external_memories: Vec<ExternalImageMemory>,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError>;
+
+    unsafe fn export_memory(
+        &self,
+        external_memory_type: ExternalMemoryType,
+        memory: &Self::Memory,
+    ) -> Result<PlatformMemory, ExternalMemoryExportError>;
+
+    unsafe fn get_image_subresource_footprint(
+        &self,
+        image: &Self::Image,
+        subresource: hal::image::Subresource,
+    ) -> hal::image::SubresourceFootprint;
+
+    /// The DRM format modifier the driver chose for `image`, `None` if the image
+    /// has not been created with DRM format modifiers.
+    unsafe fn drm_format_modifier(&self, image: &Self::Image) -> Option<hal::format::DrmModifier>;
+
+    unsafe fn map_memory(
+        &self,
+        memory: &mut Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<*mut u8, hal::device::MapError>;
+    unsafe fn unmap_memory(&self, memory: &mut Self::Memory);
+    unsafe fn flush_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory>;
+    unsafe fn invalidate_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory>;
+
+    /// Creates a buffer of `size` bytes on CPU visible memory, to transfer data
+    /// from and to memory that can not be mapped.
+    unsafe fn create_staging_buffer(&self, size: u64) -> Result<(Self::Buffer, Self::Memory), HalError>;
+
+    /// The transfers are submitted to the graphics queue, and waited for. Images
+    /// are kept in the `General` layout, where an importer expects them, and
+    /// owned by the external queue family between transfers, like the external
+    /// buffer of `copy_buffer`: the ownership is acquired before the resource is
+    /// read and released after it is written or read.
+    unsafe fn copy_buffer(
+        &self,
+        src: &Self::Buffer,
+        dst: &Self::Buffer,
+        size: u64,
+        external: ExternalBuffer,
+    ) -> Result<(), HalError>;
+    unsafe fn copy_buffer_to_image(
+        &self,
+        buffer: &Self::Buffer,
+        image: &Self::Image,
+        regions: &[BufferImageCopy],
+    ) -> Result<(), HalError>;
+    unsafe fn copy_image_to_buffer(
+        &self,
+        image: &Self::Image,
+        buffer: &Self::Buffer,
+        regions: &[BufferImageCopy],
+    ) -> Result<(), HalError>;
+
+    /// Runs `group_count` groups of `shader` with `buffers` bound as the storage
+    /// buffers of set 0, in order, and waits for it. The ownership of the payload
+    /// buffer, at binding 0, is acquired from the external queue family for the
+    /// dispatch and released to it afterwards.
+    unsafe fn dispatch(
+        &self,
+        shader: crate::compute::Shader,
+        buffers: &[&Self::Buffer],
+        push_constants: &[u32],
+        group_count: u32,
+    ) -> Result<(), HalError>;
+
+    /// Clears `image`, a single level and layer 2D color attachment of `format`,
+    /// and draws `scene` on it in a render pass, then waits for it. The image is
+    /// left in the `General` layout and released to the external queue family.
+    unsafe fn render(
+        &self,
+        image: &Self::Image,
+        format: hal::format::Format,
+        extent: hal::image::Extent,
+        scene: &crate::render::Scene,
+    ) -> Result<(), HalError>;
+
+    unsafe fn destroy_buffer(&self, buffer: Self::Buffer);
+    unsafe fn destroy_image(&self, image: Self::Image);
+    unsafe fn free_memory(&self, memory: Self::Memory);
+
+    fn wait_idle(&self) -> Result<(), hal::device::OutOfMemory>;
+}
+
+/// The external buffer of a `copy_buffer`, the other one being a staging buffer.
+/// Its ownership is acquired from the external queue family for the copy and
+/// released to it afterwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExternalBuffer {
+    Src,
+    Dst,
+}
+
+/// A `TestDevice` backed by a real gfx backend.
+pub struct HalDevice<B: hal::Backend> {
+    pub adapter: Adapter<B>,
+    pub device: B::Device,
+    queue: RefCell<HalQueue<B>>,
+}
+
+/// The graphics queue the transfers are submitted to, with the pool their
+/// command buffers are allocated from and the fence they are waited with.
+struct HalQueue<B: hal::Backend> {
+    group: QueueGroup<B>,
+    command_pool: ManuallyDrop<B::CommandPool>,
+    fence: ManuallyDrop<B::Fence>,
+}
+
+impl<B: hal::Backend> HalDevice<B> {
+    /// `queue_group` must have a queue supporting graphics.
+    pub fn new(adapter: Adapter<B>, device: B::Device, queue_group: QueueGroup<B>) -> Self {
+        let command_pool = unsafe {
+            device.create_command_pool(queue_group.family, hal::pool::CommandPoolCreateFlags::TRANSIENT)
+        }
+        .expect("Failed to create a command pool");
+        let fence = device.create_fence(false).expect("Failed to create a fence");
+        Self {
+            adapter,
+            device,
+            queue: RefCell::new(HalQueue {
+                group: queue_group,
+                command_pool: ManuallyDrop::new(command_pool),
+                fence: ManuallyDrop::new(fence),
+            }),
+        }
+    }
+
+    /// The queue family the transfers are submitted to.
+    fn queue_family(&self) -> QueueFamilyId {
+        self.queue.borrow().group.family
+    }
+
+    /// Records a command buffer with `record`, submits it and waits for it.
+    unsafe fn submit(&self, record: impl FnOnce(&mut B::CommandBuffer)) -> Result<(), HalError> {
+        let mut queue = self.queue.borrow_mut();
+        let queue = &mut *queue;
+        let mut command_buffer = queue.command_pool.allocate_one(hal::command::Level::Primary);
+        command_buffer.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+        record(&mut command_buffer);
+        command_buffer.finish();
+        queue.group.queues[0].submit(
+            iter::once(&command_buffer),
+            iter::empty(),
+            iter::empty(),
+            Some(&mut *queue.fence),
+        );
+        let waited = self.device.wait_for_fence(&queue.fence, !0);
+        let reset = self.device.reset_fence(&mut queue.fence);
+        queue.command_pool.free(iter::once(command_buffer));
+        match waited {
+            Ok(true) => reset.map_err(HalError::from),
+            Ok(false) => Err(String::from("The transfer did not complete").into()),
+            Err(err) => Err(format!("wait_for_fence: {}", err).into()),
+        }
+    }
+}
+
+impl<B: hal::Backend> Drop for HalDevice<B> {
+    fn drop(&mut self) {
+        let queue = self.queue.get_mut();
+        unsafe {
+            self.device.destroy_command_pool(ManuallyDrop::take(&mut queue.command_pool));
+            self.device.destroy_fence(ManuallyDrop::take(&mut queue.fence));
+        }
+    }
+}
+
+/// The objects a dispatch needs, created one after the other and destroyed
+/// together, whether all of them could be created or not.
+struct ComputePipeline<B: hal::Backend> {
+    module: Option<B::ShaderModule>,
+    set_layout: Option<B::DescriptorSetLayout>,
+    layout: Option<B::PipelineLayout>,
+    pipeline: Option<B::ComputePipeline>,
+    pool: Option<B::DescriptorPool>,
+    set: Option<B::DescriptorSet>,
+}
+
+impl<B: hal::Backend> Default for ComputePipeline<B> {
+    fn default() -> Self {
+        Self {
+            module: None,
+            set_layout: None,
+            layout: None,
+            pipeline: None,
+            pool: None,
+            set: None,
+        }
+    }
+}
+
+impl<B: hal::Backend> ComputePipeline<B> {
+    unsafe fn destroy(self, device: &B::Device) {
+        // The set is freed with its pool
+        if let Some(pool) = self.pool {
+            device.destroy_descriptor_pool(pool);
+        }
+        if let Some(pipeline) = self.pipeline {
+            device.destroy_compute_pipeline(pipeline);
+        }
+        if let Some(layout) = self.layout {
+            device.destroy_pipeline_layout(layout);
+        }
+        if let Some(set_layout) = self.set_layout {
+            device.destroy_descriptor_set_layout(set_layout);
+        }
+        if let Some(module) = self.module {
+            device.destroy_shader_module(module);
+        }
+    }
+}
+
+impl<B: hal::Backend> HalDevice<B> {
+    /// Creates the objects of `pipeline` for `shader`, with `buffers` written to
+    /// its descriptor set.
+    unsafe fn create_compute_pipeline(
+        &self,
+        pipeline: &mut ComputePipeline<B>,
+        shader: crate::compute::Shader,
+        buffers: &[&B::Buffer],
+        push_constant_count: usize,
+    ) -> Result<(), HalError> {
+        use hal::pso::{
+            BufferDescriptorFormat, BufferDescriptorType, DescriptorPool, DescriptorRangeDesc,
+            DescriptorSetLayoutBinding, DescriptorType, ShaderStageFlags,
+        };
+        let descriptor_type = DescriptorType::Buffer {
+            ty: BufferDescriptorType::Storage { read_only: false },
+            format: BufferDescriptorFormat::Structured { dynamic_offset: false },
+        };
+
+        let module = self
+            .device
+            .create_shader_module(&shader.spirv())
+            .map_err(|err| format!("create_shader_module: {:?}", err))?;
+        let module = pipeline.module.get_or_insert(module);
+        let set_layout = self
+            .device
+            .create_descriptor_set_layout(
+                (0..buffers.len()).map(|binding| DescriptorSetLayoutBinding {
+                    binding: binding as u32,
+                    ty: descriptor_type,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                }),
+                iter::empty(),
+            )
+            .map_err(|err| format!("create_descriptor_set_layout: {}", err))?;
+        let set_layout = pipeline.set_layout.get_or_insert(set_layout);
+        let layout = self
+            .device
+            .create_pipeline_layout(
+                iter::once(&*set_layout),
+                iter::once((ShaderStageFlags::COMPUTE, 0..(push_constant_count * 4) as u32)),
+            )
+            .map_err(|err| format!("create_pipeline_layout: {}", err))?;
+        let layout = pipeline.layout.get_or_insert(layout);
+        let entry_point = hal::pso::EntryPoint {
+            entry: "main",
+            module: &*module,
+            specialization: hal::pso::Specialization::default(),
+        };
+        let compute_pipeline = self
+            .device
+            .create_compute_pipeline(&hal::pso::ComputePipelineDesc::new(entry_point, &*layout), None)
+            .map_err(|err| format!("create_compute_pipeline: {}", err))?;
+        pipeline.pipeline = Some(compute_pipeline);
+        let pool = self
+            .device
+            .create_descriptor_pool(
+                1,
+                iter::once(DescriptorRangeDesc { ty: descriptor_type, count: buffers.len() }),
+                hal::pso::DescriptorPoolCreateFlags::empty(),
+            )
+            .map_err(|err| format!("create_descriptor_pool: {}", err))?;
+        let pool = pipeline.pool.get_or_insert(pool);
+        let mut set = pool
+            .allocate_one(&*set_layout)
+            .map_err(|err| format!("allocate_one: {}", err))?;
+        // The buffers overflow into the next bindings
+        self.device.write_descriptor_set(hal::pso::DescriptorSetWrite {
+            set: &mut set,
+            binding: 0,
+            array_offset: 0,
+            descriptors: buffers
+                .iter()
+                .map(|&buffer| hal::pso::Descriptor::Buffer(buffer, hal::buffer::SubRange::WHOLE)),
+        });
+        pipeline.set = Some(set);
+        Ok(())
+    }
+}
+
+/// The objects a render pass drawing a `render::Scene` needs, created and
+/// destroyed like the ones of `ComputePipeline`.
+struct GraphicsPipeline<B: hal::Backend> {
+    vertex_module: Option<B::ShaderModule>,
+    fragment_module: Option<B::ShaderModule>,
+    render_pass: Option<B::RenderPass>,
+    layout: Option<B::PipelineLayout>,
+    pipeline: Option<B::GraphicsPipeline>,
+    view: Option<B::ImageView>,
+    framebuffer: Option<B::Framebuffer>,
+}
+
+impl<B: hal::Backend> Default for GraphicsPipeline<B> {
+    fn default() -> Self {
+        Self {
+            vertex_module: None,
+            fragment_module: None,
+            render_pass: None,
+            layout: None,
+            pipeline: None,
+            view: None,
+            framebuffer: None,
+        }
+    }
+}
+
+impl<B: hal::Backend> GraphicsPipeline<B> {
+    unsafe fn destroy(self, device: &B::Device) {
+        if let Some(framebuffer) = self.framebuffer {
+            device.destroy_framebuffer(framebuffer);
+        }
+        if let Some(view) = self.view {
+            device.destroy_image_view(view);
+        }
+        if let Some(pipeline) = self.pipeline {
+            device.destroy_graphics_pipeline(pipeline);
+        }
+        if let Some(layout) = self.layout {
+            device.destroy_pipeline_layout(layout);
+        }
+        if let Some(render_pass) = self.render_pass {
+            device.destroy_render_pass(render_pass);
+        }
+        if let Some(module) = self.fragment_module {
+            device.destroy_shader_module(module);
+        }
+        if let Some(module) = self.vertex_module {
+            device.destroy_shader_module(module);
+        }
+    }
+}
+
+impl<B: hal::Backend> HalDevice<B> {
+    /// Creates the objects of `pipeline` drawing on `image`, with the vertices of
+    /// the triangle as vertex shader push constants.
+    unsafe fn create_graphics_pipeline(
+        &self,
+        pipeline: &mut GraphicsPipeline<B>,
+        image: &B::Image,
+        format: hal::format::Format,
+        extent: hal::image::Extent,
+    ) -> Result<(), HalError> {
+        use hal::image::Layout;
+        use hal::pass::{Attachment, AttachmentLoadOp, AttachmentOps, AttachmentStoreOp, SubpassDesc};
+        use hal::pso::ShaderStageFlags;
+
+        let (vertex_spirv, fragment_spirv) = crate::render::spirv();
+        let vertex_module = self
+            .device
+            .create_shader_module(&vertex_spirv)
+            .map_err(|err| format!("create_shader_module: {:?}", err))?;
+        let vertex_module = pipeline.vertex_module.get_or_insert(vertex_module);
+        let fragment_module = self
+            .device
+            .create_shader_module(&fragment_spirv)
+            .map_err(|err| format!("create_shader_module: {:?}", err))?;
+        let fragment_module = pipeline.fragment_module.get_or_insert(fragment_module);
+
+        // The previous content is cleared, the rendered one is left where the
+        // importer expects it
+        let attachment = Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: Layout::Undefined..Layout::General,
+        };
+        let subpass = SubpassDesc {
+            colors: &[(0, Layout::ColorAttachmentOptimal)],
+            depth_stencil: None,
+            inputs: &[],
+            resolves: &[],
+            preserves: &[],
+        };
+        let render_pass = self
+            .device
+            .create_render_pass(iter::once(attachment), iter::once(subpass), iter::empty())
+            .map_err(|err| format!("create_render_pass: {}", err))?;
+        let render_pass = pipeline.render_pass.get_or_insert(render_pass);
+        let layout = self
+            .device
+            .create_pipeline_layout(iter::empty(), iter::once((ShaderStageFlags::VERTEX, 0..24)))
+            .map_err(|err| format!("create_pipeline_layout: {}", err))?;
+        let layout = pipeline.layout.get_or_insert(layout);
+
+        let mut desc = hal::pso::GraphicsPipelineDesc::new(
+            hal::pso::PrimitiveAssemblerDesc::Vertex {
+                buffers: &[],
+                attributes: &[],
+                input_assembler: hal::pso::InputAssemblerDesc::new(hal::pso::Primitive::TriangleList),
+                vertex: entry_point::<B>(&*vertex_module),
+                tessellation: None,
+                geometry: None,
+            },
+            hal::pso::Rasterizer::FILL,
+            Some(entry_point::<B>(&*fragment_module)),
+            &*layout,
+            hal::pass::Subpass { index: 0, main_pass: &*render_pass },
+        );
+        desc.blender.targets.push(hal::pso::ColorBlendDesc {
+            mask: hal::pso::ColorMask::ALL,
+            blend: None,
+        });
+        let rect = render_area(extent);
+        desc.baked_states.viewport = Some(hal::pso::Viewport { rect, depth: 0.0..1.0 });
+        desc.baked_states.scissor = Some(rect);
+        let graphics_pipeline = self
+            .device
+            .create_graphics_pipeline(&desc, None)
+            .map_err(|err| format!("create_graphics_pipeline: {}", err))?;
+        pipeline.pipeline = Some(graphics_pipeline);
+
+        let view = self
+            .device
+            .create_image_view(
+                image,
+                hal::image::ViewKind::D2,
+                format,
+                hal::format::Swizzle::NO,
+                hal::image::Usage::COLOR_ATTACHMENT,
+                image_range(),
+            )
+            .map_err(|err| format!("create_image_view: {}", err))?;
+        pipeline.view = Some(view);
+        let framebuffer = self
+            .device
+            .create_framebuffer(
+                &*render_pass,
+                iter::once(hal::image::FramebufferAttachment {
+                    usage: hal::image::Usage::COLOR_ATTACHMENT,
+                    view_caps: hal::image::ViewCapabilities::empty(),
+                    format,
+                }),
+                extent,
+            )
+            .map_err(|err| format!("create_framebuffer: {}", err))?;
+        pipeline.framebuffer = Some(framebuffer);
+        Ok(())
+    }
+}
+
+fn entry_point<B: hal::Backend>(module: &B::ShaderModule) -> hal::pso::EntryPoint<'_, B> {
+    hal::pso::EntryPoint {
+        entry: "main",
+        module,
+        specialization: hal::pso::Specialization::default(),
+    }
+}
+
+/// The whole of an image of `extent`.
+fn render_area(extent: hal::image::Extent) -> hal::pso::Rect {
+    hal::pso::Rect {
+        x: 0,
+        y: 0,
+        w: extent.width as i16,
+        h: extent.height as i16,
+    }
+}
+
+/// Barriers of transfers on images cover every plane through the color aspect.
+fn image_range() -> hal::image::SubresourceRange {
+    hal::image::SubresourceRange {
+        aspects: hal::format::Aspects::COLOR,
+        level_start: 0,
+        level_count: None,
+        layer_start: 0,
+        layer_count: None,
+    }
+}
+
+/// `VK_QUEUE_FAMILY_EXTERNAL`, the queues of every other user of external
+/// memory. gfx-hal hands queue family ids over to Vulkan as they are.
+const QUEUE_FAMILY_EXTERNAL: QueueFamilyId = QueueFamilyId(!0u32 as usize - 1);
+
+/// Transfers the ownership of `image`, kept in the `General` layout, between
+/// the queue families `families`, one of them being `QUEUE_FAMILY_EXTERNAL`.
+/// The accesses before a release and after an acquire are ignored by the other
+/// side of the transfer.
+fn image_ownership_barrier<B: hal::Backend>(
+    command_buffer: &mut B::CommandBuffer,
+    stages: std::ops::Range<hal::pso::PipelineStage>,
+    accesses: std::ops::Range<hal::image::Access>,
+    image: &B::Image,
+    families: std::ops::Range<QueueFamilyId>,
+) {
+    use hal::image::Layout;
+    unsafe {
+        command_buffer.pipeline_barrier(
+            stages,
+            hal::memory::Dependencies::empty(),
+            iter::once(hal::memory::Barrier::Image {
+                states: (accesses.start, Layout::General)..(accesses.end, Layout::General),
+                target: image,
+                range: image_range(),
+                families: Some(families),
+            }),
+        )
+    }
+}
+
+/// The buffer counterpart of `image_ownership_barrier`.
+fn buffer_ownership_barrier<B: hal::Backend>(
+    command_buffer: &mut B::CommandBuffer,
+    stages: std::ops::Range<hal::pso::PipelineStage>,
+    accesses: std::ops::Range<hal::buffer::Access>,
+    buffer: &B::Buffer,
+    families: std::ops::Range<QueueFamilyId>,
+) {
+    unsafe {
+        command_buffer.pipeline_barrier(
+            stages,
+            hal::memory::Dependencies::empty(),
+            iter::once(hal::memory::Barrier::Buffer {
+                states: accesses,
+                target: buffer,
+                range: hal::buffer::SubRange::WHOLE,
+                families: Some(families),
+            }),
+        )
+    }
+}
+
+/// Makes the transfer writes visible to the host.
+fn transfer_to_host_barrier<B: hal::Backend>(command_buffer: &mut B::CommandBuffer) {
+    unsafe {
+        command_buffer.pipeline_barrier(
+            hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::HOST,
+            hal::memory::Dependencies::empty(),
+            iter::once(hal::memory::Barrier::AllBuffers(
+                hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::HOST_READ,
+            )),
+        )
+    }
+}
+
+impl<B: hal::Backend> TestDevice for HalDevice<B> {
+    type Buffer = B::Buffer;
+    type Image = B::Image;
+    type Memory = B::Memory;
+
+    fn external_buffer_properties(
+        &self,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        external_memory_type: ExternalBufferMemoryType,
+    ) -> ExternalMemoryProperties {
+        self.adapter
+            .physical_device
+            .external_buffer_properties(usage, sparse, external_memory_type)
+    }
+
+    fn external_image_properties(
+        &self,
+        format: hal::format::Format,
+        dimensions: u8,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        view_caps: hal::image::ViewCapabilities,
+        external_memory_type: ExternalMemoryType,
+    ) -> Result<ExternalMemoryProperties, ExternalImagePropertiesError> {
+        self.adapter.physical_device.external_image_properties(
+            format,
+            dimensions,
+            tiling,
+            usage,
+            view_caps,
+            external_memory_type,
+        )
+    }
+
+    fn adapter_info(&self) -> hal::adapter::AdapterInfo {
+        self.adapter.info.clone()
+    }
+
+    fn memory_properties(&self) -> hal::adapter::MemoryProperties {
+        self.adapter.physical_device.memory_properties()
+    }
+
+    fn format_properties(&self, format: Option<hal::format::Format>) -> hal::format::Properties {
+        self.adapter.physical_device.format_properties(format)
+    }
+
+    fn min_imported_host_pointer_alignment(&self) -> u64 {
+        self.adapter
+            .physical_device
+            .properties()
+            .external_memory_limits
+            .min_imported_host_pointer_alignment
+    }
+
+    fn non_coherent_atom_size(&self) -> u64 {
+        self.adapter.physical_device.properties().limits.non_coherent_atom_size as u64
+    }
+
+    unsafe fn create_allocate_external_buffer(
+        &self,
+        external_memory_type: ExternalBufferMemoryType,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError> {
+        self.device
+            .create_allocate_external_buffer(external_memory_type, usage, sparse, type_mask, size)
+    }
+
+    unsafe fn import_external_buffer(
+        &self,
+        external_memory: ExternalBufferMemory,
+        usage: hal::buffer::Usage,
+        sparse: hal::memory::SparseFlags,
+        type_mask: u32,
+        size: u64,
+    ) -> Result<(Self::Buffer, Self::Memory), ExternalResourceError> {
+        self.device
+            .import_external_buffer(external_memory, usage, sparse, type_mask, size)
+    }
+
+    unsafe fn create_allocate_external_image(
+        &self,
+        external_memory_type: ExternalImageMemoryType,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError> {
+        self.device.create_allocate_external_image(
+            external_memory_type,
+            kind,
+            mip_levels,
+            format,
+            tiling,
+            usage,
+            sparse,
+            view_caps,
+            type_mask,
+        )
+    }
+
+    unsafe fn import_external_image(
+        &self,
+        external_memory: ExternalImageMemory,
+        kind: hal::image::Kind,
+        mip_levels: hal::image::Level,
+        format: hal::format::Format,
+        tiling: hal::image::Tiling,
+        usage: hal::image::Usage,
+        sparse: hal::memory::SparseFlags,
+        view_caps: hal::image::ViewCapabilities,
+        type_mask: u32,
+    ) -> Result<(Self::Image, Self::Memory), ExternalResourceError> {
+        self.device.import_external_image(
+            external_memory,
+            kind,
+            mip_levels,
+            format,
+            tiling,
+            usage,
+            sparse,
+            view_caps,
+            type_mask,
+        )
+    }
+
+    /// gfx-hal binds an image to a single memory object.
+    fn disjoint_images(&self) -> bool {
+        false
+    }
+
+    unsafe fn create_allocate_external_disjoint_image(
+        &self,
+        _external_memory_type: ExternalImageMemoryType,
+        _kind: hal::image::Kind,
+        _mip_levels: hal::image::Level,
+        _format: hal::format::Format,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _sparse: hal::memory::SparseFlags,
+        _view_caps: hal::image::ViewCapabilities,
+        _type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError> {
+        unreachable!("Disjoint images are not supported")
+    }
+
+    unsafe fn import_external_disjoint_image(
+        &self,
+        _external_memories: Vec<ExternalImageMemory>,
+        _kind: hal::image::Kind,
+        _mip_levels: hal::image::Level,
+        _format: hal::format::Format,
+        _tiling: hal::image::Tiling,
+        _usage: hal::image::Usage,
+        _sparse: hal::memory::SparseFlags,
+        _view_caps: hal::image::ViewCapabilities,
+        _type_mask: u32,
+    ) -> Result<(Self::Image, Vec<Self::Memory>), ExternalResourceError> {
+        unreachable!("Disjoint images are not supported")
+    }
+
+    unsafe fn export_memory(
+        &self,
+        external_memory_type: ExternalMemoryType,
+        memory: &Self::Memory,
+    ) -> Result<PlatformMemory, ExternalMemoryExportError> {
+        self.device.export_memory(external_memory_type, memory)
+    }
+
+    unsafe fn get_image_subresource_footprint(
+        &self,
+        image: &Self::Image,
+        subresource: hal::image::Subresource,
+    ) -> hal::image::SubresourceFootprint {
+        self.device.get_image_subresource_footprint(image, subresource)
+    }
+
+    unsafe fn drm_format_modifier(&self, image: &Self::Image) -> Option<hal::format::DrmModifier> {
+        self.device.drm_format_modifier(image)
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: &mut Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<*mut u8, hal::device::MapError> {
+        self.device.map_memory(memory, segment)
+    }
+
+    unsafe fn unmap_memory(&self, memory: &mut Self::Memory) {
+        self.device.unmap_memory(memory)
+    }
+
+    unsafe fn flush_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory> {
+        self.device
+            .flush_mapped_memory_ranges(std::iter::once((memory, segment)))
+    }
+
+    unsafe fn invalidate_memory(
+        &self,
+        memory: &Self::Memory,
+        segment: hal::memory::Segment,
+    ) -> Result<(), hal::device::OutOfMemory> {
+        self.device
+            .invalidate_mapped_memory_ranges(std::iter::once((memory, segment)))
+    }
+
+    unsafe fn create_staging_buffer(&self, size: u64) -> Result<(Self::Buffer, Self::Memory), HalError> {
+        let mut buffer = self
+            .device
+            .create_buffer(
+                size,
+                hal::buffer::Usage::TRANSFER_SRC
+                    | hal::buffer::Usage::TRANSFER_DST
+                    | hal::buffer::Usage::STORAGE,
+                hal::memory::SparseFlags::empty(),
+            )
+            .map_err(|err| format!("create_buffer: {}", err))?;
+        let requirements = self.device.get_buffer_requirements(&buffer);
+        let memory_type = self
+            .memory_properties()
+            .memory_types
+            .iter()
+            .enumerate()
+            .find(|(index, memory_type)| {
+                requirements.type_mask & (1 << index) != 0
+                    && memory_type.properties.contains(hal::memory::Properties::CPU_VISIBLE)
+            })
+            .map(|(index, _)| index);
+        let memory = match memory_type {
+            Some(memory_type) => self
+                .device
+                .allocate_memory(hal::MemoryTypeId(memory_type), requirements.size)
+                .map_err(|err| format!("allocate_memory: {}", err)),
+            None => Err(String::from("No CPU visible memory type for the staging buffer")),
+        };
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                self.device.destroy_buffer(buffer);
+                return Err(err.into());
+            }
+        };
+        if let Err(err) = self.device.bind_buffer_memory(&memory, 0, &mut buffer) {
+            self.device.destroy_buffer(buffer);
+            self.device.free_memory(memory);
+            return Err(format!("bind_buffer_memory: {}", err).into());
+        }
+        Ok((buffer, memory))
+    }
+
+    unsafe fn copy_buffer(
+        &self,
+        src: &Self::Buffer,
+        dst: &Self::Buffer,
+        size: u64,
+        external: ExternalBuffer,
+    ) -> Result<(), HalError> {
+        use hal::buffer::Access;
+        use hal::pso::PipelineStage;
+        let family = self.queue_family();
+        let (buffer, access) = match external {
+            ExternalBuffer::Src => (src, Access::TRANSFER_READ),
+            ExternalBuffer::Dst => (dst, Access::TRANSFER_WRITE),
+        };
+        self.submit(|command_buffer| {
+            buffer_ownership_barrier::<B>(
+                command_buffer,
+                PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+                Access::empty()..access,
+                buffer,
+                QUEUE_FAMILY_EXTERNAL..family,
+            );
+            command_buffer.copy_buffer(
+                src,
+                dst,
+                iter::once(hal::command::BufferCopy { src: 0, dst: 0, size }),
+            );
+            buffer_ownership_barrier::<B>(
+                command_buffer,
+                PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+                access..Access::empty(),
+                buffer,
+                family..QUEUE_FAMILY_EXTERNAL,
+            );
+            transfer_to_host_barrier::<B>(command_buffer);
+        })
+    }
+
+    unsafe fn copy_buffer_to_image(
+        &self,
+        buffer: &Self::Buffer,
+        image: &Self::Image,
+        regions: &[BufferImageCopy],
+    ) -> Result<(), HalError> {
+        use hal::image::{Access, Layout};
+        use hal::pso::PipelineStage;
+        let family = self.queue_family();
+        self.submit(|command_buffer| {
+            // Every texel gets written, the previous content and its ownership
+            // can be discarded
+            command_buffer.pipeline_barrier(
+                hal::pso::PipelineStage::TOP_OF_PIPE..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                iter::once(hal::memory::Barrier::Image {
+                    states: (Access::empty(), Layout::Undefined)..(Access::TRANSFER_WRITE, Layout::General),
+                    target: image,
+                    range: image_range(),
+                    families: None,
+                }),
+            );
+            command_buffer.copy_buffer_to_image(buffer, image, Layout::General, regions.iter().cloned());
+            image_ownership_barrier::<B>(
+                command_buffer,
+                PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+                Access::TRANSFER_WRITE..Access::empty(),
+                image,
+                family..QUEUE_FAMILY_EXTERNAL,
+            );
+        })
+    }
+
+    unsafe fn copy_image_to_buffer(
+        &self,
+        image: &Self::Image,
+        buffer: &Self::Buffer,
+        regions: &[BufferImageCopy],
+    ) -> Result<(), HalError> {
+        use hal::image::{Access, Layout};
+        use hal::pso::PipelineStage;
+        let family = self.queue_family();
+        self.submit(|command_buffer| {
+            image_ownership_barrier::<B>(
+                command_buffer,
+                PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+                Access::empty()..Access::TRANSFER_READ,
+                image,
+                QUEUE_FAMILY_EXTERNAL..family,
+            );
+            command_buffer.copy_image_to_buffer(image, Layout::General, buffer, regions.iter().cloned());
+            image_ownership_barrier::<B>(
+                command_buffer,
+                PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+                Access::TRANSFER_READ..Access::empty(),
+                image,
+                family..QUEUE_FAMILY_EXTERNAL,
+            );
+            transfer_to_host_barrier::<B>(command_buffer);
+        })
+    }
+
+    unsafe fn dispatch(
+        &self,
+        shader: crate::compute::Shader,
+        buffers: &[&Self::Buffer],
+        push_constants: &[u32],
+        group_count: u32,
+    ) -> Result<(), HalError> {
+        use hal::buffer::Access;
+        use hal::pso::PipelineStage;
+        let family = self.queue_family();
+        let mut pipeline = ComputePipeline::<B>::default();
+        let result = self.create_compute_pipeline(&mut pipeline, shader, buffers, push_constants.len());
+        let result = result.and_then(|()| {
+            let layout = pipeline.layout.as_ref().unwrap();
+            self.submit(|command_buffer| {
+                command_buffer.bind_compute_pipeline(pipeline.pipeline.as_ref().unwrap());
+                command_buffer.bind_compute_descriptor_sets(
+                    layout,
+                    0,
+                    pipeline.set.as_ref().into_iter(),
+                    iter::empty(),
+                );
+                command_buffer.push_compute_constants(layout, 0, push_constants);
+                buffer_ownership_barrier::<B>(
+                    command_buffer,
+                    PipelineStage::TOP_OF_PIPE..PipelineStage::COMPUTE_SHADER,
+                    Access::empty()..Access::SHADER_READ | Access::SHADER_WRITE,
+                    buffers[0],
+                    QUEUE_FAMILY_EXTERNAL..family,
+                );
+                command_buffer.dispatch([group_count, 1, 1]);
+                buffer_ownership_barrier::<B>(
+                    command_buffer,
+                    PipelineStage::COMPUTE_SHADER..PipelineStage::BOTTOM_OF_PIPE,
+                    Access::SHADER_READ | Access::SHADER_WRITE..Access::empty(),
+                    buffers[0],
+                    family..QUEUE_FAMILY_EXTERNAL,
+                );
+                command_buffer.pipeline_barrier(
+                    hal::pso::PipelineStage::COMPUTE_SHADER..hal::pso::PipelineStage::HOST,
+                    hal::memory::Dependencies::empty(),
+                    iter::once(hal::memory::Barrier::AllBuffers(
+                        hal::buffer::Access::SHADER_WRITE..hal::buffer::Access::HOST_READ,
+                    )),
+                );
+            })
+        });
+        pipeline.destroy(&self.device);
+        result
+    }
+
+    unsafe fn render(
+        &self,
+        image: &Self::Image,
+        format: hal::format::Format,
+        extent: hal::image::Extent,
+        scene: &crate::render::Scene,
+    ) -> Result<(), HalError> {
+        use hal::image::Access;
+        use hal::pso::PipelineStage;
+        let family = self.queue_family();
+        let mut pipeline = GraphicsPipeline::<B>::default();
+        let result = self.create_graphics_pipeline(&mut pipeline, image, format, extent);
+        let result = result.and_then(|()| {
+            let mut clear_color = [0.0; 4];
+            for (color, &channel) in clear_color.iter_mut().zip(crate::render::CLEAR_COLOR.iter()) {
+                *color = channel as f32 / 255.0;
+            }
+            self.submit(|command_buffer| {
+                command_buffer.begin_render_pass(
+                    pipeline.render_pass.as_ref().unwrap(),
+                    pipeline.framebuffer.as_ref().unwrap(),
+                    render_area(extent),
+                    iter::once(hal::command::RenderAttachmentInfo {
+                        image_view: pipeline.view.as_ref().unwrap(),
+                        clear_value: hal::command::ClearValue {
+                            color: hal::command::ClearColor { float32: clear_color },
+                        },
+                    }),
+                    hal::command::SubpassContents::Inline,
+                );
+                command_buffer.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
+                command_buffer.push_graphics_constants(
+                    pipeline.layout.as_ref().unwrap(),
+                    hal::pso::ShaderStageFlags::VERTEX,
+                    0,
+                    &scene.push_constants(),
+                );
+                command_buffer.draw(0..3, 0..1);
+                command_buffer.end_render_pass();
+                // The later submissions acquire the rendered texels back
+                image_ownership_barrier::<B>(
+                    command_buffer,
+                    PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::BOTTOM_OF_PIPE,
+                    Access::COLOR_ATTACHMENT_WRITE..Access::empty(),
+                    image,
+                    family..QUEUE_FAMILY_EXTERNAL,
+                );
+            })
+        });
+        pipeline.destroy(&self.device);
+        result
+    }
+
+    unsafe fn destroy_buffer(&self, buffer: Self::Buffer) {
+        self.device.destroy_buffer(buffer)
+    }
+
+    unsafe fn destroy_image(&self, image: Self::Image) {
+        self.device.destroy_image(image)
+    }
+
+    unsafe fn free_memory(&self, memory: Self::Memory) {
+        self.device.free_memory(memory)
+    }
+
+    fn wait_idle(&self) -> Result<(), hal::device::OutOfMemory> {
+        self.device.wait_idle()
+    }
+}
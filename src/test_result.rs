@@ -0,0 +1,270 @@
+use gfx_hal as hal;
+use hal::external_memory::{
+    ExternalImagePropertiesError, ExternalMemoryExportError, ExternalMemoryProperties,
+    ExternalResourceError,
+};
+
+use crate::{Parameters, ResourceError};
+
+/// Why a stage has not been run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /// A previous stage failed or has been skipped.
+    NotReached,
+    /// The queried properties lack the flag the stage requires.
+    NotSupported {
+        properties: ExternalMemoryProperties,
+        required: ExternalMemoryProperties,
+    },
+    /// The stage can not be run with the selected options.
+    Unsupported(String),
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotReached => f.write_str("not reached"),
+            Self::NotSupported {
+                properties,
+                required,
+            } => write!(f, "{:?} not in {:?}", required, properties),
+            Self::Unsupported(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// An error returned by one of the calls made by a test.
+#[derive(Debug)]
+pub enum HalError {
+    ImageProperties(ExternalImagePropertiesError),
+    Resource(ExternalResourceError),
+    Export(ExternalMemoryExportError),
+    Map(hal::device::MapError),
+    OutOfMemory(hal::device::OutOfMemory),
+    WrongResource(ResourceError),
+    Other(String),
+}
+
+impl std::fmt::Display for HalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ImageProperties(err) => err.fmt(f),
+            Self::Resource(err) => err.fmt(f),
+            Self::Export(err) => err.fmt(f),
+            Self::Map(err) => err.fmt(f),
+            Self::OutOfMemory(err) => err.fmt(f),
+            Self::WrongResource(err) => err.fmt(f),
+            Self::Other(err) => f.write_str(err),
+        }
+    }
+}
+
+impl From<ExternalImagePropertiesError> for HalError {
+    fn from(err: ExternalImagePropertiesError) -> Self {
+        Self::ImageProperties(err)
+    }
+}
+
+impl From<ExternalResourceError> for HalError {
+    fn from(err: ExternalResourceError) -> Self {
+        Self::Resource(err)
+    }
+}
+
+impl From<ExternalMemoryExportError> for HalError {
+    fn from(err: ExternalMemoryExportError) -> Self {
+        Self::Export(err)
+    }
+}
+
+impl From<hal::device::MapError> for HalError {
+    fn from(err: hal::device::MapError) -> Self {
+        Self::Map(err)
+    }
+}
+
+impl From<hal::device::OutOfMemory> for HalError {
+    fn from(err: hal::device::OutOfMemory) -> Self {
+        Self::OutOfMemory(err)
+    }
+}
+
+impl From<ResourceError> for HalError {
+    fn from(err: ResourceError) -> Self {
+        Self::WrongResource(err)
+    }
+}
+
+impl From<String> for HalError {
+    fn from(err: String) -> Self {
+        Self::Other(err)
+    }
+}
+
+impl From<std::io::Error> for HalError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Other(err.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub struct TestError {
+    /// The call that returned the error, like `export_memory`.
+    pub call: &'static str,
+    pub error: HalError,
+    /// The queried external memory properties, if the query succeeded.
+    pub properties: Option<ExternalMemoryProperties>,
+}
+
+impl std::fmt::Display for TestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}`: {}", self.call, self.error)?;
+        if let Some(properties) = self.properties {
+            write!(f, " (properties: {:?})", properties)?;
+        }
+        Ok(())
+    }
+}
+
+pub enum TestResult {
+    Success,
+    Skipped(SkipReason),
+    Failed(TestError),
+}
+
+impl TestResult {
+    /// Logs the error returned by `call` and records it.
+    pub fn failed(
+        call: &'static str,
+        error: impl Into<HalError>,
+        properties: Option<ExternalMemoryProperties>,
+    ) -> Self {
+        let error = TestError {
+            call,
+            error: error.into(),
+            properties,
+        };
+        log::error!("Error on {}", error);
+        Self::Failed(error)
+    }
+
+    pub fn not_supported(
+        properties: ExternalMemoryProperties,
+        required: ExternalMemoryProperties,
+    ) -> Self {
+        Self::Skipped(SkipReason::NotSupported {
+            properties,
+            required,
+        })
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+}
+
+impl std::fmt::Debug for TestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => f.write_str(":heavy_check_mark:"),
+            Self::Skipped(reason) => write!(f, ":fast_forward: {}", reason),
+            Self::Failed(error) => write!(f, ":x: {}", error),
+        }
+    }
+}
+
+pub struct Tests {
+    pub name: String,
+    pub parameters: Parameters,
+    /// The queried external memory properties, `None` if the query failed.
+    pub properties: Option<ExternalMemoryProperties>,
+    /// Seed of the random payload of a buffer.
+    pub seed: Option<u64>,
+    /// `None` if no memory type matches the memory filter of the test case.
+    pub memory_type: Option<crate::MemoryTypeInfo>,
+    pub create_allocate_external_resource: TestResult,
+    /// The exporter writing the data of the test case on its resource.
+    pub write_data: TestResult,
+    pub export_memory: TestResult,
+    pub import_external_resource: TestResult,
+    pub data_check: TestResult,
+    pub export_from_imported: TestResult,
+    pub reimport_external_resource: TestResult,
+    pub reimport_data_check: TestResult,
+    /// Importing a DmaBuf image with a wrong DRM format modifier must fail.
+    pub wrong_drm_modifier_import: TestResult,
+    /// The importer must see the same plane footprints as the exporter.
+    pub footprint_check: TestResult,
+}
+
+impl Tests {
+    pub fn new(name: String, parameters: Parameters) -> Self {
+        let wrong_drm_modifier_import = match parameters.drm_modifiers() {
+            Some(_) => TestResult::Skipped(SkipReason::NotReached),
+            None => TestResult::Skipped(SkipReason::Unsupported(
+                "Only for DmaBuf images created with DRM format modifiers".into(),
+            )),
+        };
+        let footprint_check = match parameters {
+            Parameters::Image { tiling: hal::image::Tiling::Optimal, .. } => {
+                TestResult::Skipped(SkipReason::Unsupported("Only for linear images".into()))
+            }
+            Parameters::Image { .. } => TestResult::Skipped(SkipReason::NotReached),
+            Parameters::Buffer { .. } => {
+                TestResult::Skipped(SkipReason::Unsupported("Only for images".into()))
+            }
+        };
+        Self {
+            name,
+            parameters,
+            properties: None,
+            seed: None,
+            memory_type: None,
+            create_allocate_external_resource: TestResult::Skipped(SkipReason::NotReached),
+            write_data: TestResult::Skipped(SkipReason::NotReached),
+            export_memory: TestResult::Skipped(SkipReason::NotReached),
+            import_external_resource: TestResult::Skipped(SkipReason::NotReached),
+            data_check: TestResult::Skipped(SkipReason::NotReached),
+            export_from_imported: TestResult::Skipped(SkipReason::NotReached),
+            reimport_external_resource: TestResult::Skipped(SkipReason::NotReached),
+            reimport_data_check: TestResult::Skipped(SkipReason::NotReached),
+            wrong_drm_modifier_import,
+            footprint_check,
+        }
+    }
+
+    pub fn stages(&self) -> [(&'static str, &TestResult); 10] {
+        [
+            ("create_allocate_external_resource", &self.create_allocate_external_resource),
+            ("write_data", &self.write_data),
+            ("export_memory", &self.export_memory),
+            ("import_external_resource", &self.import_external_resource),
+            ("data_check", &self.data_check),
+            ("export_from_imported", &self.export_from_imported),
+            ("reimport_external_resource", &self.reimport_external_resource),
+            ("reimport_data_check", &self.reimport_data_check),
+            ("wrong_drm_modifier_import", &self.wrong_drm_modifier_import),
+            ("footprint_check", &self.footprint_check),
+        ]
+    }
+
+    pub fn failed(&self) -> bool {
+        self.stages().iter().any(|(_, result)| result.is_failed())
+    }
+}
+
+impl std::fmt::Debug for Tests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        if let Some(memory_type) = &self.memory_type {
+            writeln!(f, "memory_type:{}", memory_type)?;
+        }
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed:{}", seed)?;
+        }
+        for (stage, result) in self.stages().iter() {
+            writeln!(f, "{}:{:?}", stage, result)?;
+        }
+        Ok(())
+    }
+}
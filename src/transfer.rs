@@ -0,0 +1,132 @@
+//! Data written and checked through a staging buffer and copies on the graphics
+//! queue, for memory that can not be mapped and optimally tiled images.
+//!
+//! The exporter fills a staging buffer and copies it to its resource, the
+//! importer copies its resource to another staging buffer and checks it. Image
+//! subresources are packed one after the other in the staging buffers.
+
+use gfx_hal as hal;
+use hal::external_memory::{ExternalImageMemoryType, ExternalMemoryType};
+
+use crate::image_data;
+use crate::{
+    check_memory, write_memory, ExternalBuffer, ExternalResource, HalError, MemoryFilter, Parameters,
+    Resource, TestCase, TestDevice, Verification,
+};
+
+/// Writes the data of `test_case` on `resource` through a staging buffer.
+pub fn write_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &ExternalResource<D>,
+) -> Result<(), HalError> {
+    match image_data::image_layout(&test_case.parameters) {
+        Some(layout) => {
+            let (footprints, size) = layout.packed_footprints();
+            let mut staging = staging_buffer(device, size)?;
+            image_data::write_texels(device, staging_memory(&mut staging), &footprints, &layout, test_case.image_content)?;
+            unsafe { device.copy_buffer_to_image(staging.buffer()?, resource.image()?, &layout.copy_regions(&footprints)) }
+        }
+        None => {
+            let len = test_case.data.payload_size()?;
+            let mut staging = staging_buffer(device, len)?;
+            write_memory(device, staging_memory(&mut staging), &mut test_case.data.reader()?, len)?;
+            unsafe { device.copy_buffer(staging.buffer()?, resource.buffer()?, len, ExternalBuffer::Dst) }
+        }
+    }
+}
+
+/// Checks the data of `test_case` on `resource` through a staging buffer. See
+/// `image_data::check_texels` for `mismatch_dir`.
+pub fn check_data<D: TestDevice>(
+    device: &D,
+    test_case: &TestCase,
+    resource: &ExternalResource<D>,
+    mismatch_dir: Option<&std::path::Path>,
+) -> Result<(), HalError> {
+    match image_data::image_layout(&test_case.parameters) {
+        Some(layout) => {
+            let (footprints, size) = layout.packed_footprints();
+            let mut staging = staging_buffer(device, size)?;
+            unsafe { device.copy_image_to_buffer(resource.image()?, staging.buffer()?, &layout.copy_regions(&footprints)) }?;
+            image_data::check_texels(
+                device,
+                staging_memory(&mut staging),
+                &footprints,
+                &layout,
+                test_case.image_content,
+                mismatch_dir,
+                &test_case.name,
+            )
+        }
+        None => {
+            let len = test_case.data.payload_size()?;
+            let mut staging = staging_buffer(device, len)?;
+            unsafe { device.copy_buffer(resource.buffer()?, staging.buffer()?, len, ExternalBuffer::Src) }?;
+            check_memory(device, staging_memory(&mut staging), &mut test_case.data.reader()?, len)
+        }
+    }
+}
+
+/// A staging buffer of at least `len` bytes, rounded up to the non-coherent atom
+/// size so that the flushed and invalidated ranges stay inside it.
+fn staging_buffer<D: TestDevice>(device: &D, len: u64) -> Result<ExternalResource<D>, HalError> {
+    let atom_size = device.non_coherent_atom_size().max(1);
+    let size = ((len.max(1) + atom_size - 1) / atom_size) * atom_size;
+    let (buffer, memory) = unsafe { device.create_staging_buffer(size) }?;
+    Ok(ExternalResource::new(device, Resource::Buffer(buffer), memory))
+}
+
+fn staging_memory<'a, D: TestDevice>(staging: &'a mut ExternalResource<D>) -> &'a mut D::Memory {
+    // Staging buffers are never kept mapped
+    staging.memory_mut().unwrap()
+}
+
+/// Buffer and `Rgba8Srgb` optimally tiled image cases for the handle types
+/// shared between devices, on device local memory types and checked through
+/// transfers.
+pub fn transfer_test_cases() -> Vec<TestCase> {
+    let mut external_memory_types = Vec::new();
+    #[cfg(unix)]
+    external_memory_types.push(("OPAQUE_FD", ExternalMemoryType::OpaqueFd, ExternalImageMemoryType::OpaqueFd));
+    #[cfg(windows)]
+    external_memory_types.push(("OPAQUE_WIN32", ExternalMemoryType::OpaqueWin32, ExternalImageMemoryType::OpaqueWin32));
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    external_memory_types.push(("DMA_BUF", ExternalMemoryType::DmaBuf, ExternalImageMemoryType::DmaBuf(Vec::new())));
+
+    let mut test_cases = Vec::new();
+    for (name, buffer_memory_type, image_memory_type) in external_memory_types {
+        test_cases.push(transfer_test_case(
+            format!("{} TRANSFER", name),
+            Parameters::Buffer {
+                external_memory_type: buffer_memory_type,
+                buffer_usage: hal::buffer::Usage::TRANSFER_SRC | hal::buffer::Usage::TRANSFER_DST,
+                buffer_flags: hal::memory::SparseFlags::empty(),
+            },
+        ));
+        test_cases.push(transfer_test_case(
+            format!("{} OPTIMAL TRANSFER", name),
+            Parameters::Image {
+                external_memory_type: image_memory_type,
+                kind: hal::image::Kind::D2(crate::WIDTH, crate::HEIGHT, 1, 1),
+                mip_levels: 1,
+                format: hal::format::Format::Rgba8Srgb,
+                tiling: hal::image::Tiling::Optimal,
+                usage: hal::image::Usage::TRANSFER_SRC
+                    | hal::image::Usage::TRANSFER_DST
+                    | hal::image::Usage::SAMPLED,
+                sparse: hal::memory::SparseFlags::empty(),
+                view_caps: hal::image::ViewCapabilities::empty(),
+            },
+        ));
+    }
+    test_cases
+}
+
+fn transfer_test_case(name: String, parameters: Parameters) -> TestCase {
+    TestCase {
+        memory_filter: MemoryFilter::DEVICE_LOCAL,
+        verification: Verification::Transfer,
+        ..TestCase::new(name, parameters)
+    }
+}
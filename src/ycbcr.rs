@@ -0,0 +1,76 @@
+//! Multi-planar YCbCr formats used for video interop.
+
+use gfx_hal as hal;
+use hal::format::Format;
+
+use crate::image_data::PlaneDesc;
+use crate::{image_parameters, TestCase, TestDevice};
+
+pub struct YcbcrFormat {
+    /// The common name of the format.
+    pub name: &'static str,
+    pub format: Format,
+    pub planes: &'static [PlaneDesc],
+}
+
+pub const YCBCR_FORMATS: [YcbcrFormat; 3] = [
+    YcbcrFormat {
+        name: "NV12",
+        format: Format::G8B8r82plane420Unorm,
+        planes: &[
+            PlaneDesc { width_divisor: 1, height_divisor: 1, texel_size: 1 },
+            PlaneDesc { width_divisor: 2, height_divisor: 2, texel_size: 2 },
+        ],
+    },
+    YcbcrFormat {
+        name: "P010",
+        format: Format::G10x6B10x6r10x62plane420Unorm3pack16,
+        planes: &[
+            PlaneDesc { width_divisor: 1, height_divisor: 1, texel_size: 2 },
+            PlaneDesc { width_divisor: 2, height_divisor: 2, texel_size: 4 },
+        ],
+    },
+    YcbcrFormat {
+        name: "I420",
+        format: Format::G8B8R83plane420Unorm,
+        planes: &[
+            PlaneDesc { width_divisor: 1, height_divisor: 1, texel_size: 1 },
+            PlaneDesc { width_divisor: 2, height_divisor: 2, texel_size: 1 },
+            PlaneDesc { width_divisor: 2, height_divisor: 2, texel_size: 1 },
+        ],
+    },
+];
+
+pub fn ycbcr_format(format: Format) -> Option<&'static YcbcrFormat> {
+    YCBCR_FORMATS.iter().find(|ycbcr_format| ycbcr_format.format == format)
+}
+
+/// OpaqueFd and DmaBuf cases for each YCbCr format, plus one case per DRM format
+/// modifier. Disjoint planes are covered by `disjoint`.
+pub fn ycbcr_test_cases<D: TestDevice>(device: &D) -> Vec<TestCase> {
+    let mut test_cases = Vec::new();
+    #[cfg(unix)]
+    for ycbcr_format in YCBCR_FORMATS.iter() {
+        test_cases.push(TestCase::new(
+            format!("{} OPAQUE_FD", ycbcr_format.name),
+            image_parameters(
+                hal::external_memory::ExternalImageMemoryType::OpaqueFd,
+                ycbcr_format.format,
+            ),
+        ));
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            test_cases.push(TestCase::new(
+                format!("{} DMA_BUF", ycbcr_format.name),
+                image_parameters(
+                    hal::external_memory::ExternalImageMemoryType::DmaBuf(Vec::new()),
+                    ycbcr_format.format,
+                ),
+            ));
+            test_cases.extend(crate::drm_modifier_test_cases(device, &[ycbcr_format.format]));
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = device;
+    test_cases
+}